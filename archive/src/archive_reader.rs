@@ -1,60 +1,639 @@
-use std::io::{Cursor, Read, Seek};
+use std::io::{Cursor, Read, Seek, SeekFrom};
 
-use mla::ArchiveReader;
+use bincode::Decode;
+use image::RgbaImage;
+use mla::{config::ArchiveReaderConfig, ArchiveReader};
+use x25519_dalek::StaticSecret;
 
 use crate::{
-    constants::BINCODE_CONFIG,
-    errors::{NextTileChunkError, PlacedArchiveError},
-    structures::{DecodedTilePlacement, Meta, StoredTilePlacement},
+    chunk_tiles::ChunkTiles,
+    constants::{BINCODE_CONFIG, COMPACT_BINCODE_CONFIG, FORMAT_VERSION},
+    errors::{
+        CanvasReconstructionError, NextTileChunkError, PixelHistoryError, PlacedArchiveError,
+        ReadError, VerificationError,
+    },
+    legacy::{MetaV0, StoredTilePlacementV0},
+    pixel_index::PixelIndex,
+    structures::{
+        BoundingBox, ChunkFootprint, DecodedTilePlacement, Meta, StoredTilePlacement, TileEncoding,
+    },
+    tile_encoding::{decode_compact_tiles, decode_frame_grid_tiles, CompactTileIndex},
 };
 
 pub struct PlacedArchiveReader<'a, R: Read + Seek> {
-    mla: ArchiveReader<'a, R>,
+    pub(crate) mla: ArchiveReader<'a, R>,
+    /// Format version the archive was written with, see `FORMAT_VERSION`
+    pub format_version: u32,
     pub meta: Meta,
     current_tile_chunk_id: Option<u32>,
-    current_tile_chunk_data: Option<Cursor<Vec<u8>>>,
+    current_tile_chunk_data: Option<ChunkTiles>,
+    // Placer of each tile in the current chunk as user id plus one, with zero for unknown placers
+    current_tile_chunk_users: Option<Vec<u32>>,
 }
 
 impl<'a, R: Read + Seek + 'a> PlacedArchiveReader<'a, R> {
     pub fn new(reader: R) -> Result<Self, PlacedArchiveError> {
-        let mut mla = match ArchiveReader::new(reader) {
-            Ok(mla) => mla,
+        let mla = ArchiveReader::new(reader).map_err(PlacedArchiveError::MLAReadError)?;
+
+        Self::from_mla(mla)
+    }
+
+    /// Opens an archive that may be encrypted for any of the recipients owning `private_keys`.
+    pub fn with_private_keys(
+        reader: R,
+        private_keys: &[StaticSecret],
+    ) -> Result<Self, PlacedArchiveError> {
+        let mut config = ArchiveReaderConfig::new();
+        config.add_private_keys(private_keys);
+        let mla =
+            ArchiveReader::from_config(reader, config).map_err(PlacedArchiveError::MLAReadError)?;
+
+        Self::from_mla(mla)
+    }
+
+    fn from_mla(mut mla: ArchiveReader<'a, R>) -> Result<Self, PlacedArchiveError> {
+        let format_version = match mla.get_file("version".to_string()) {
+            Ok(Some(mut version_file)) => {
+                match bincode::decode_from_std_read(&mut version_file.data, BINCODE_CONFIG) {
+                    Ok(format_version) => format_version,
+                    Err(_) => return Err(PlacedArchiveError::CouldNotDecodeVersionFile),
+                }
+            }
+            // Archives written before versioning was introduced
+            Ok(None) => 0,
             Err(err) => return Err(PlacedArchiveError::MLAReadError(err)),
         };
 
+        if format_version > FORMAT_VERSION {
+            return Err(PlacedArchiveError::UnsupportedFormatVersion(format_version));
+        }
+
         let mut meta_file = match mla.get_file("meta".to_string()) {
             Ok(Some(meta_file)) => meta_file,
             Ok(None) => return Err(PlacedArchiveError::MissingMetaFile),
             Err(_) => return Err(PlacedArchiveError::MissingMetaFile),
         };
 
-        let meta: Meta = match bincode::decode_from_std_read(&mut meta_file.data, BINCODE_CONFIG) {
+        let meta = match format_version {
+            0 => bincode::decode_from_std_read::<MetaV0, _, _>(&mut meta_file.data, BINCODE_CONFIG)
+                .map(Meta::from),
+            _ => bincode::decode_from_std_read(&mut meta_file.data, BINCODE_CONFIG),
+        };
+        let meta = match meta {
             Ok(meta) => meta,
             Err(_) => return Err(PlacedArchiveError::CouldNotDecodeMetaFile),
         };
 
         Ok(Self {
             mla,
+            format_version,
             meta,
             current_tile_chunk_id: None,
             current_tile_chunk_data: None,
+            current_tile_chunk_users: None,
+        })
+    }
+
+    /// Moves the reader to the tile at `tile_index`, counting from the first tile in the archive.
+    pub fn seek_to_tile_index(&mut self, tile_index: u64) -> std::io::Result<()> {
+        self.seek(SeekFrom::Start(
+            tile_index * StoredTilePlacement::encoded_size() as u64,
+        ))?;
+
+        Ok(())
+    }
+
+    /// Moves the reader to the first tile placed at or after `ms_since_epoch` and returns its index.
+    /// If every tile was placed before `ms_since_epoch`, the reader is moved to the end of the archive.
+    pub fn seek_to_ms(&mut self, ms_since_epoch: u64) -> std::io::Result<u64> {
+        let chunk_id = self
+            .meta
+            .chunk_descs
+            .partition_point(|desc| desc.up_to_ms_since_epoch < ms_since_epoch);
+        let first_tile_index_in_chunk = self.meta.chunk_descs[..chunk_id]
+            .iter()
+            .fold(0, |acc, desc| acc + desc.num_tiles as u64);
+
+        if chunk_id == self.meta.chunk_descs.len() {
+            self.seek_to_tile_index(first_tile_index_in_chunk)?;
+            return Ok(first_tile_index_in_chunk);
+        }
+
+        self.load_chunk_by_id(chunk_id as u32)?;
+        self.current_tile_chunk_id = Some(chunk_id as u32);
+
+        let chunk_data = self.current_tile_chunk_data.as_mut().unwrap();
+        let encoded_size = StoredTilePlacement::encoded_size();
+
+        // Binary search for the first tile in the chunk placed at or after the requested time
+        let mut low = 0;
+        let mut high = chunk_data.len() as usize / encoded_size;
+        while low < high {
+            let mid = (low + high) / 2;
+            let tile = chunk_data.tile(mid).map_err(std::io::Error::other)?;
+
+            if tile.ms_since_epoch < ms_since_epoch {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        chunk_data.set_position((low * encoded_size) as u64);
+
+        Ok(first_tile_index_in_chunk + low as u64)
+    }
+
+    /// Moves the reader back by one tile and returns that tile, so the next call to `next()` returns it again.
+    /// Returns `None` at the start of the archive.
+    pub fn previous_tile(&mut self) -> Option<DecodedTilePlacement> {
+        let encoded_size = StoredTilePlacement::encoded_size() as u64;
+
+        loop {
+            let position = self.current_tile_chunk_data.as_ref()?.position();
+            if position >= encoded_size {
+                let tile_index_in_chunk = (position / encoded_size - 1) as usize;
+                let chunk_data = self.current_tile_chunk_data.as_mut()?;
+                chunk_data.set_position(tile_index_in_chunk as u64 * encoded_size);
+                let tile = chunk_data.tile(tile_index_in_chunk).ok()?;
+
+                return self.decode_tile(tile, tile_index_in_chunk).ok();
+            }
+
+            // At the start of the current chunk, continue from the end of the one before it
+            let previous_chunk_id = self.current_tile_chunk_id?.checked_sub(1)?;
+            self.load_chunk_by_id(previous_chunk_id).ok()?;
+            self.current_tile_chunk_id = Some(previous_chunk_id);
+            let chunk_data = self.current_tile_chunk_data.as_mut()?;
+            chunk_data.set_position(chunk_data.len());
+        }
+    }
+
+    /// Iterates over the tiles before the current position, starting with the most recent one.
+    /// Seek to the end of the archive first to walk through all of it backwards.
+    pub fn rev_tiles(&mut self) -> ReverseTiles<'_, 'a, R> {
+        ReverseTiles { reader: self }
+    }
+
+    /// Reconstructs the canvas after every tile placed at or before `ms_since_epoch` has been applied.
+    /// The canvas has the size in effect at `ms_since_epoch`.
+    /// Starts from the closest preceding snapshot (if the archive contains snapshots), so only the tiles placed after it are replayed.
+    /// Afterwards, the reader is positioned at the first tile placed after `ms_since_epoch`.
+    pub fn canvas_at(
+        &mut self,
+        ms_since_epoch: u64,
+    ) -> Result<RgbaImage, CanvasReconstructionError> {
+        let num_of_complete_chunks = self
+            .meta
+            .chunk_descs
+            .partition_point(|desc| desc.up_to_ms_since_epoch <= ms_since_epoch);
+
+        let mut canvas = None;
+        let mut tile_index = 0;
+        for chunk_id in (0..num_of_complete_chunks).rev() {
+            if let Some(snapshot) = self.load_snapshot(chunk_id as u32)? {
+                canvas = Some(snapshot);
+                tile_index = self.meta.chunk_descs[..=chunk_id]
+                    .iter()
+                    .fold(0, |acc, desc| acc + desc.num_tiles as u64);
+                break;
+            }
+        }
+
+        let canvas_size = self.meta.get_canvas_size_at(ms_since_epoch).unwrap();
+        let canvas_dimensions = (canvas_size.width as u32, canvas_size.height as u32);
+        let mut canvas = match canvas {
+            Some(canvas) if canvas.dimensions() == canvas_dimensions => canvas,
+            snapshot => {
+                let mut canvas = RgbaImage::new(canvas_dimensions.0, canvas_dimensions.1);
+                canvas.fill(0xff);
+                // The canvas may have been resized since the snapshot was taken
+                if let Some(snapshot) = snapshot {
+                    image::imageops::replace(&mut canvas, &snapshot, 0, 0);
+                }
+                canvas
+            }
+        };
+
+        self.seek_to_tile_index(tile_index)
+            .map_err(CanvasReconstructionError::CouldNotReadTiles)?;
+
+        for tile in Iterator::by_ref(self) {
+            if tile.ms_since_epoch > ms_since_epoch {
+                break;
+            }
+
+            canvas.put_pixel(tile.x as u32, tile.y as u32, image::Rgba(tile.color));
+            tile_index += 1;
+        }
+
+        self.seek_to_tile_index(tile_index)
+            .map_err(CanvasReconstructionError::CouldNotReadTiles)?;
+
+        Ok(canvas)
+    }
+
+    /// Loads the footprint of a chunk, or returns `None` if the archive doesn't record it.
+    pub fn chunk_footprint(
+        &mut self,
+        chunk_id: u32,
+    ) -> Result<Option<ChunkFootprint>, PlacedArchiveError> {
+        let mut footprint_file = match self.mla.get_file(format!("footprints/{}", chunk_id)) {
+            Ok(Some(footprint_file)) => footprint_file,
+            Ok(None) => return Ok(None),
+            Err(err) => return Err(PlacedArchiveError::MLAReadError(err)),
+        };
+
+        match bincode::decode_from_std_read(&mut footprint_file.data, BINCODE_CONFIG) {
+            Ok(footprint) => Ok(Some(footprint)),
+            Err(_) => Err(PlacedArchiveError::CouldNotDecodeFootprintFile),
+        }
+    }
+
+    /// Returns the ids of the chunks that may place tiles within `region`, in order.
+    /// Chunks without a footprint are assumed to touch every pixel.
+    pub fn chunks_touching(&mut self, region: BoundingBox) -> Result<Vec<u32>, PlacedArchiveError> {
+        let mut chunk_ids = Vec::new();
+        for chunk_id in 0..self.meta.chunk_descs.len() as u32 {
+            if self.chunk_may_touch(chunk_id, &region)? {
+                chunk_ids.push(chunk_id);
+            }
+        }
+
+        Ok(chunk_ids)
+    }
+
+    /// Returns false if the chunk is known not to place any tile within `region`.
+    fn chunk_may_touch(
+        &mut self,
+        chunk_id: u32,
+        region: &BoundingBox,
+    ) -> Result<bool, PlacedArchiveError> {
+        Ok(match self.chunk_footprint(chunk_id)? {
+            Some(footprint) => footprint.touches_region(region),
+            None => true,
         })
     }
 
+    /// Returns every tile placed within `region`, in the order they were placed.
+    /// Only the chunks that the archive's pixel index and chunk footprints don't rule out are read.
+    /// Afterwards, the reader is back at the position it had before.
+    pub fn pixel_history(
+        &mut self,
+        region: BoundingBox,
+    ) -> Result<Vec<DecodedTilePlacement>, PixelHistoryError> {
+        // Archives written before the pixel index was introduced rely on chunk footprints alone
+        let chunk_ids = match self.load_pixel_index()? {
+            Some(pixel_index) => pixel_index.chunk_ids_touching(&region),
+            None => self.meta.chunk_descs.iter().map(|desc| desc.id).collect(),
+        };
+
+        let position = self
+            .stream_position()
+            .map_err(PixelHistoryError::CouldNotReadTiles)?;
+
+        let mut history = Vec::new();
+        for chunk_id in chunk_ids {
+            if !self
+                .chunk_may_touch(chunk_id, &region)
+                .map_err(PixelHistoryError::CouldNotLoadFootprint)?
+            {
+                continue;
+            }
+
+            self.load_chunk_by_id(chunk_id)
+                .map_err(|err| PixelHistoryError::CouldNotReadTiles(err.into()))?;
+            self.current_tile_chunk_id = Some(chunk_id);
+
+            let num_tiles = self.current_tile_chunk_data.as_ref().unwrap().len() as usize
+                / StoredTilePlacement::encoded_size();
+            for tile_index_in_chunk in 0..num_tiles {
+                let tile = self
+                    .current_tile_chunk_data
+                    .as_mut()
+                    .unwrap()
+                    .tile(tile_index_in_chunk)
+                    .map_err(|err| {
+                        PixelHistoryError::CouldNotReadTiles(std::io::Error::other(err))
+                    })?;
+
+                if region.contains(tile.x, tile.y) {
+                    history.push(
+                        self.decode_tile(tile, tile_index_in_chunk)
+                            .map_err(|err| PixelHistoryError::CouldNotReadTiles(err.into()))?,
+                    );
+                }
+            }
+        }
+
+        self.seek(SeekFrom::Start(position))
+            .map_err(PixelHistoryError::CouldNotReadTiles)?;
+
+        Ok(history)
+    }
+
+    /// Checks that every chunk is present and matches its description and checksum, that tiles are in chronological order
+    /// and only use known colors, and that the number of tiles matches the metadata. Returns every problem found.
+    pub fn verify(&mut self) -> Result<(), Vec<VerificationError>> {
+        let mut problems = Vec::new();
+        let mut total_num_of_tiles = 0;
+        let mut last_ms_since_epoch = 0;
+
+        for (index, chunk_desc) in self.meta.chunk_descs.clone().into_iter().enumerate() {
+            if chunk_desc.id != index as u32 {
+                problems.push(VerificationError::UnexpectedChunkId {
+                    expected: index as u32,
+                    found: chunk_desc.id,
+                });
+            }
+
+            let buf = match self.read_chunk_file(chunk_desc.id) {
+                Ok(buf) => buf,
+                Err(NextTileChunkError::MissingChunkFile) => {
+                    problems.push(VerificationError::MissingChunk(chunk_desc.id));
+                    continue;
+                }
+                Err(_) => {
+                    problems.push(VerificationError::UnreadableChunk(chunk_desc.id));
+                    continue;
+                }
+            };
+
+            if let Some(checksum) = chunk_desc.checksum {
+                if crc32fast::hash(&buf) != checksum {
+                    problems.push(VerificationError::ChecksumMismatch(chunk_desc.id));
+                }
+            }
+
+            let buf = match decode_chunk_file(&self.meta, self.format_version, buf) {
+                Ok(buf) if buf.len() % StoredTilePlacement::encoded_size() == 0 => buf,
+                _ => {
+                    problems.push(VerificationError::UnreadableChunk(chunk_desc.id));
+                    continue;
+                }
+            };
+
+            let num_tiles = (buf.len() / StoredTilePlacement::encoded_size()) as u64;
+            if num_tiles != chunk_desc.num_tiles as u64 {
+                problems.push(VerificationError::TileCountMismatch {
+                    chunk_id: chunk_desc.id,
+                    expected: chunk_desc.num_tiles as u64,
+                    found: num_tiles,
+                });
+            }
+            total_num_of_tiles += num_tiles;
+
+            let mut is_ordered = true;
+            for encoded_tile in buf.chunks_exact(StoredTilePlacement::encoded_size()) {
+                let tile: StoredTilePlacement =
+                    match bincode::decode_from_slice(encoded_tile, BINCODE_CONFIG) {
+                        Ok((tile, _)) => tile,
+                        Err(_) => {
+                            problems.push(VerificationError::UnreadableChunk(chunk_desc.id));
+                            break;
+                        }
+                    };
+
+                if !self.meta.color_id_to_tuple.contains_key(&tile.color_index) {
+                    problems.push(VerificationError::UnknownColor {
+                        chunk_id: chunk_desc.id,
+                        color_index: tile.color_index,
+                    });
+                }
+
+                is_ordered &= tile.ms_since_epoch >= last_ms_since_epoch
+                    && tile.ms_since_epoch <= chunk_desc.up_to_ms_since_epoch;
+                last_ms_since_epoch = tile.ms_since_epoch;
+            }
+            if !is_ordered {
+                problems.push(VerificationError::UnorderedTiles(chunk_desc.id));
+            }
+        }
+
+        if total_num_of_tiles != self.meta.total_tile_placements {
+            problems.push(VerificationError::TotalTileCountMismatch {
+                expected: self.meta.total_tile_placements,
+                found: total_num_of_tiles,
+            });
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(problems),
+        }
+    }
+
+    /// Returns the hashes identifying the placers of tiles, indexed by `DecodedTilePlacement::user_id`.
+    /// Returns `None` if the archive doesn't record who placed tiles.
+    pub fn user_table(&mut self) -> Result<Option<Vec<String>>, PlacedArchiveError> {
+        if self.meta.num_users.is_none() {
+            return Ok(None);
+        }
+
+        let mut user_table_file = match self.mla.get_file("users".to_string()) {
+            Ok(Some(user_table_file)) => user_table_file,
+            Ok(None) => return Err(PlacedArchiveError::MissingUserTableFile),
+            Err(err) => return Err(PlacedArchiveError::MLAReadError(err)),
+        };
+
+        match bincode::decode_from_std_read(&mut user_table_file.data, BINCODE_CONFIG) {
+            Ok(user_table) => Ok(Some(user_table)),
+            Err(_) => Err(PlacedArchiveError::CouldNotDecodeUserTableFile),
+        }
+    }
+
+    /// Decodes a tile of the current chunk.
+    fn decode_tile(
+        &self,
+        tile: StoredTilePlacement,
+        tile_index_in_chunk: usize,
+    ) -> Result<DecodedTilePlacement, ReadError> {
+        decode_tile_of_chunk(
+            &self.meta,
+            self.current_tile_chunk_id.unwrap_or(0),
+            self.current_tile_chunk_users.as_deref(),
+            tile,
+            tile_index_in_chunk,
+        )
+    }
+
+    /// Reads every tile of a chunk, without moving the reader.
+    pub fn read_chunk(&mut self, chunk_id: u32) -> Result<Vec<DecodedTilePlacement>, ReadError> {
+        let files = self.read_chunk_files(chunk_id)?;
+
+        decode_chunk_files(&self.meta, self.format_version, chunk_id, files)
+    }
+
+    /// Reads a chunk's tile file and the placers of its tiles as stored in the archive, to be decoded with
+    /// `decode_chunk_files()`.
+    pub(crate) fn read_chunk_files(&mut self, chunk_id: u32) -> Result<ChunkFiles, ReadError> {
+        let tiles = self
+            .read_chunk_file(chunk_id)
+            .map_err(|err| chunk_read_error(chunk_id, err))?;
+        let users = match self.meta.num_users {
+            Some(_) => self
+                .read_chunk_users_file(chunk_id)
+                .map_err(|err| chunk_read_error(chunk_id, err))?,
+            None => None,
+        };
+
+        Ok(ChunkFiles { tiles, users })
+    }
+
+    /// Iterates over the tiles from the current position like the reader itself, but yields an error instead of ending
+    /// early if the archive is broken. Ends after the first error.
+    pub fn try_iter(&mut self) -> TryTiles<'_, 'a, R> {
+        TryTiles {
+            reader: self,
+            failed: false,
+        }
+    }
+
+    fn try_next(&mut self) -> Option<Result<DecodedTilePlacement, ReadError>> {
+        let encoded_size = StoredTilePlacement::encoded_size();
+
+        loop {
+            if let Some(data) = &self.current_tile_chunk_data {
+                if data.position() < data.len() {
+                    break;
+                }
+            }
+
+            let next_chunk_id = self.current_tile_chunk_id.map_or(0, |id| id + 1);
+            match self.get_next_chunk_data() {
+                Ok(_) => {}
+                Err(NextTileChunkError::OutOfChunks) => return None,
+                Err(err) => return Some(Err(chunk_read_error(next_chunk_id, err))),
+            }
+        }
+
+        let chunk_id = self.current_tile_chunk_id?;
+        let chunk_data = self.current_tile_chunk_data.as_mut()?;
+        let position = chunk_data.position() as usize;
+        let chunk_len = chunk_data.len() as usize;
+
+        if position + encoded_size > chunk_len {
+            chunk_data.set_position(chunk_len as u64);
+            return Some(Err(ReadError::TruncatedRecord(chunk_id)));
+        }
+
+        chunk_data.set_position((position + encoded_size) as u64);
+        let tile = match chunk_data.tile(position / encoded_size) {
+            Ok(tile) => tile,
+            Err(_) => return Some(Err(ReadError::CouldNotDecodeChunk(chunk_id))),
+        };
+
+        Some(self.decode_tile(tile, position / encoded_size))
+    }
+
+    fn load_pixel_index(&mut self) -> Result<Option<PixelIndex>, PixelHistoryError> {
+        let mut pixel_index_file = match self.mla.get_file("pixel_index".to_string()) {
+            Ok(Some(pixel_index_file)) => pixel_index_file,
+            Ok(None) => return Ok(None),
+            Err(err) => return Err(PixelHistoryError::CouldNotFetchIndexFile(err)),
+        };
+
+        match bincode::decode_from_std_read(&mut pixel_index_file.data, BINCODE_CONFIG) {
+            Ok(pixel_index) => Ok(Some(pixel_index)),
+            Err(_) => Err(PixelHistoryError::CouldNotDecodeIndexFile),
+        }
+    }
+
+    fn load_snapshot(
+        &mut self,
+        tile_chunk_id: u32,
+    ) -> Result<Option<RgbaImage>, CanvasReconstructionError> {
+        let mut snapshot_file = match self.mla.get_file(format!("snapshots/{}", tile_chunk_id)) {
+            Ok(Some(snapshot_file)) => snapshot_file,
+            Ok(None) => return Ok(None),
+            Err(err) => return Err(CanvasReconstructionError::CouldNotFetchSnapshotFile(err)),
+        };
+
+        let mut buf = Vec::with_capacity(snapshot_file.size as usize);
+        std::io::copy(&mut snapshot_file.data, &mut buf)
+            .map_err(CanvasReconstructionError::CouldNotReadTiles)?;
+
+        match image::load_from_memory_with_format(&buf, image::ImageFormat::Png) {
+            Ok(snapshot) => Ok(Some(snapshot.into_rgba8())),
+            Err(err) => Err(CanvasReconstructionError::CouldNotDecodeSnapshot(err)),
+        }
+    }
+
     fn load_chunk_by_id(&mut self, tile_chunk_id: u32) -> Result<(), NextTileChunkError> {
+        let buf = self.read_chunk_file(tile_chunk_id)?;
+        let tiles = match self.meta.tile_encoding {
+            // Compact chunks are only decoded block by block as they're read
+            TileEncoding::Compact => {
+                let index = self.load_compact_tile_index(tile_chunk_id, &buf)?;
+                ChunkTiles::compact(buf, index)
+            }
+            _ => ChunkTiles::fixed(decode_chunk_file(&self.meta, self.format_version, buf)?),
+        };
+
+        self.current_tile_chunk_users = match self.meta.num_users {
+            Some(_) => self
+                .read_chunk_users_file(tile_chunk_id)?
+                .map(|buf| decode_chunk_users(&buf, tiles.len() as usize))
+                .transpose()?,
+            None => None,
+        };
+        self.current_tile_chunk_data = Some(tiles);
+
+        Ok(())
+    }
+
+    /// Loads the offset index of a compact chunk, or builds it if the chunk was written without one.
+    fn load_compact_tile_index(
+        &mut self,
+        tile_chunk_id: u32,
+        buf: &[u8],
+    ) -> Result<CompactTileIndex, NextTileChunkError> {
+        let mut index_file = match self.mla.get_file(format!("tile_offsets/{}", tile_chunk_id)) {
+            Ok(Some(index_file)) => index_file,
+            Ok(None) => {
+                return CompactTileIndex::build(buf)
+                    .map_err(|_| NextTileChunkError::CouldNotDecodeChunkFile)
+            }
+            Err(err) => return Err(NextTileChunkError::CouldNotFetchChunkFile(err)),
+        };
+
+        bincode::decode_from_std_read(&mut index_file.data, COMPACT_BINCODE_CONFIG)
+            .map_err(|_| NextTileChunkError::CouldNotDecodeChunkFile)
+    }
+
+    /// Returns the contents of a chunk's tile file, as stored in the archive.
+    fn read_chunk_file(&mut self, tile_chunk_id: u32) -> Result<Vec<u8>, NextTileChunkError> {
         let tile_chunk_file_name = format!("tiles/{}", tile_chunk_id);
 
-        let mut current_tile_chunk_file = match self.mla.get_file(tile_chunk_file_name) {
+        let mut tile_chunk_file = match self.mla.get_file(tile_chunk_file_name) {
             Ok(Some(tile_chunk_file)) => tile_chunk_file,
             Ok(None) => return Err(NextTileChunkError::MissingChunkFile),
             Err(err) => return Err(NextTileChunkError::CouldNotFetchChunkFile(err)),
         };
 
-        let mut buf = Vec::with_capacity(current_tile_chunk_file.size as usize);
-        std::io::copy(&mut current_tile_chunk_file.data, &mut buf).unwrap();
-        self.current_tile_chunk_data = Some(Cursor::new(buf));
+        let mut buf = Vec::with_capacity(tile_chunk_file.size as usize);
+        std::io::copy(&mut tile_chunk_file.data, &mut buf)
+            .map_err(|err| NextTileChunkError::CouldNotFetchChunkFile(err.into()))?;
 
-        Ok(())
+        Ok(buf)
+    }
+
+    /// Returns the contents of the file holding the placers of a chunk's tiles, if any of them were recorded.
+    fn read_chunk_users_file(
+        &mut self,
+        tile_chunk_id: u32,
+    ) -> Result<Option<Vec<u8>>, NextTileChunkError> {
+        let mut user_file = match self.mla.get_file(format!("users/{}", tile_chunk_id)) {
+            Ok(Some(user_file)) => user_file,
+            Ok(None) => return Ok(None),
+            Err(err) => return Err(NextTileChunkError::CouldNotFetchChunkFile(err)),
+        };
+
+        let mut buf = Vec::with_capacity(user_file.size as usize);
+        std::io::copy(&mut user_file.data, &mut buf)
+            .map_err(|err| NextTileChunkError::CouldNotFetchChunkFile(err.into()))?;
+
+        Ok(Some(buf))
     }
 
     fn get_next_chunk_data(&mut self) -> Result<(), NextTileChunkError> {
@@ -72,16 +651,147 @@ impl<'a, R: Read + Seek + 'a> PlacedArchiveReader<'a, R> {
                 self.current_tile_chunk_id = Some(tile_chunk_id);
                 Ok(())
             }
-            Err(err) => return Err(err),
+            Err(err) => Err(err),
         }
     }
 }
 
+/// A chunk's tile file and the file holding the placers of its tiles, as stored in an archive.
+pub(crate) struct ChunkFiles {
+    pub tiles: Vec<u8>,
+    pub users: Option<Vec<u8>>,
+}
+
+/// Decodes every tile of a chunk from its files, for readers that fetch them on their own.
+pub(crate) fn decode_chunk_files(
+    meta: &Meta,
+    format_version: u32,
+    chunk_id: u32,
+    files: ChunkFiles,
+) -> Result<Vec<DecodedTilePlacement>, ReadError> {
+    let buf = decode_chunk_file(meta, format_version, files.tiles)
+        .map_err(|err| chunk_read_error(chunk_id, err))?;
+    let users = match (meta.num_users, files.users) {
+        (Some(_), Some(users)) => Some(
+            decode_chunk_users(&users, buf.len()).map_err(|err| chunk_read_error(chunk_id, err))?,
+        ),
+        _ => None,
+    };
+
+    let encoded_size = StoredTilePlacement::encoded_size();
+    if buf.len() % encoded_size != 0 {
+        return Err(ReadError::TruncatedRecord(chunk_id));
+    }
+
+    buf.chunks_exact(encoded_size)
+        .enumerate()
+        .map(|(tile_index_in_chunk, encoded_tile)| {
+            let (tile, _) = bincode::decode_from_slice(encoded_tile, BINCODE_CONFIG)
+                .map_err(|_| ReadError::CouldNotDecodeChunk(chunk_id))?;
+
+            decode_tile_of_chunk(meta, chunk_id, users.as_deref(), tile, tile_index_in_chunk)
+        })
+        .collect()
+}
+
+/// Converts the contents of a chunk's tile file into consecutive `StoredTilePlacement`s in the current layout.
+fn decode_chunk_file(
+    meta: &Meta,
+    format_version: u32,
+    buf: Vec<u8>,
+) -> Result<Vec<u8>, NextTileChunkError> {
+    // Older archives are converted to the current layout, so seeking and reading work the same for every version
+    let buf = match format_version {
+        0 => convert_legacy_tiles::<StoredTilePlacementV0>(buf)?,
+        _ => buf,
+    };
+
+    match meta.tile_encoding {
+        TileEncoding::Fixed => Ok(buf),
+        TileEncoding::Compact => {
+            decode_compact_tiles(&buf).map_err(|_| NextTileChunkError::CouldNotDecodeChunkFile)
+        }
+        TileEncoding::FrameGrid { frame_ms } => decode_frame_grid_tiles(&buf, frame_ms)
+            .map_err(|_| NextTileChunkError::CouldNotDecodeChunkFile),
+    }
+}
+
+/// Decodes the placers of a chunk's tiles, given the length of the chunk's tiles in the current layout.
+fn decode_chunk_users(mut buf: &[u8], tile_buf_len: usize) -> Result<Vec<u32>, NextTileChunkError> {
+    let num_tiles = tile_buf_len / StoredTilePlacement::encoded_size();
+    let mut users = Vec::with_capacity(num_tiles);
+    for _ in 0..num_tiles {
+        let user: u32 = bincode::decode_from_std_read(&mut buf, COMPACT_BINCODE_CONFIG)
+            .map_err(|_| NextTileChunkError::CouldNotDecodeChunkFile)?;
+        users.push(user);
+    }
+
+    Ok(users)
+}
+
+/// Decodes a tile of a chunk, given the placers of the chunk's tiles if they were recorded.
+fn decode_tile_of_chunk(
+    meta: &Meta,
+    chunk_id: u32,
+    users: Option<&[u32]>,
+    tile: StoredTilePlacement,
+    tile_index_in_chunk: usize,
+) -> Result<DecodedTilePlacement, ReadError> {
+    let user_id = match users {
+        Some(users) => users[tile_index_in_chunk].checked_sub(1),
+        None => None,
+    };
+
+    let color = match meta.color_id_to_tuple.get(&tile.color_index) {
+        Some(color) => *color,
+        None => {
+            return Err(ReadError::UnknownColor {
+                chunk_id,
+                color_index: tile.color_index,
+            })
+        }
+    };
+
+    Ok(DecodedTilePlacement {
+        x: tile.x,
+        y: tile.y,
+        ms_since_epoch: tile.ms_since_epoch,
+        color,
+        user_id,
+    })
+}
+
+fn chunk_read_error(chunk_id: u32, err: NextTileChunkError) -> ReadError {
+    match err {
+        NextTileChunkError::OutOfChunks | NextTileChunkError::MissingChunkFile => {
+            ReadError::MissingChunk(chunk_id)
+        }
+        NextTileChunkError::CouldNotFetchChunkFile(err) => {
+            ReadError::CouldNotFetchChunk(chunk_id, err)
+        }
+        NextTileChunkError::CouldNotDecodeChunkFile => ReadError::CouldNotDecodeChunk(chunk_id),
+    }
+}
+
+fn convert_legacy_tiles<T: Decode + Into<StoredTilePlacement>>(
+    buf: Vec<u8>,
+) -> Result<Vec<u8>, NextTileChunkError> {
+    let mut legacy_tiles = Cursor::new(buf);
+    let mut converted_buf = Vec::new();
+    while legacy_tiles.position() < legacy_tiles.get_ref().len() as u64 {
+        let tile: T = bincode::decode_from_std_read(&mut legacy_tiles, BINCODE_CONFIG)
+            .map_err(|_| NextTileChunkError::CouldNotDecodeChunkFile)?;
+        tile.into().write_into(&mut converted_buf);
+    }
+
+    Ok(converted_buf)
+}
+
 impl<'a, R: Read + Seek> Read for PlacedArchiveReader<'a, R> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         match &mut self.current_tile_chunk_data {
             Some(ref mut data) => {
-                if data.position() == data.get_ref().len() as u64 {
+                if data.position() == data.len() {
                     match self.get_next_chunk_data() {
                         Ok(_) => self.read(buf),
                         Err(_) => Ok(0),
@@ -123,11 +833,8 @@ impl<'a, R: Read + Seek> Seek for PlacedArchiveReader<'a, R> {
                         Ok(_) => {
                             self.current_tile_chunk_id = Some(new_current_tile_chunk_id);
                         }
-                        Err(_) => {
-                            return Err(std::io::Error::new(
-                                std::io::ErrorKind::Other,
-                                "Could not load chunk",
-                            ));
+                        Err(err) => {
+                            return Err(err.into());
                         }
                     };
                 }
@@ -136,18 +843,12 @@ impl<'a, R: Read + Seek> Seek for PlacedArchiveReader<'a, R> {
                     + (pos_in_tiles - current_tile_offset)
                         * StoredTilePlacement::encoded_size() as u64;
 
-                match self
-                    .current_tile_chunk_data
+                self.current_tile_chunk_data
                     .as_mut()
                     .unwrap()
-                    .seek(std::io::SeekFrom::Start(remaining_pos))
-                {
-                    Ok(_) => Ok(pos),
-                    Err(_) => Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "Could not seek within chunk",
-                    )),
-                }
+                    .set_position(remaining_pos);
+
+                Ok(pos)
             }
             std::io::SeekFrom::Current(pos) => {
                 let current_byte_offset_within_chunk = match &self.current_tile_chunk_data {
@@ -159,7 +860,7 @@ impl<'a, R: Read + Seek> Seek for PlacedArchiveReader<'a, R> {
                     .meta
                     .chunk_descs
                     .iter()
-                    .take(self.current_tile_chunk_id.unwrap() as usize)
+                    .take(self.current_tile_chunk_id.unwrap_or(0) as usize)
                     .fold(0, |acc, desc| {
                         acc + desc.num_tiles as u64 * StoredTilePlacement::encoded_size() as u64
                     });
@@ -190,22 +891,40 @@ impl<'a, R: Read + Seek> Iterator for PlacedArchiveReader<'a, R> {
     type Item = DecodedTilePlacement;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let tile_placement: StoredTilePlacement =
-            match bincode::decode_from_std_read(self, BINCODE_CONFIG) {
-                Ok(tile_placement) => tile_placement,
-                Err(_) => return None,
-            };
+        self.try_next()?.ok()
+    }
+}
 
-        Some(DecodedTilePlacement {
-            x: tile_placement.x,
-            y: tile_placement.y,
-            ms_since_epoch: tile_placement.ms_since_epoch,
-            color: *self
-                .meta
-                .color_id_to_tuple
-                .get(&tile_placement.color_index)
-                .unwrap(),
-        })
+/// Iterator over the tiles from a reader's position, yielding errors. Created by `PlacedArchiveReader::try_iter()`.
+pub struct TryTiles<'r, 'a, R: Read + Seek> {
+    reader: &'r mut PlacedArchiveReader<'a, R>,
+    failed: bool,
+}
+
+impl<'r, 'a, R: Read + Seek + 'a> Iterator for TryTiles<'r, 'a, R> {
+    type Item = Result<DecodedTilePlacement, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let result = self.reader.try_next()?;
+        self.failed = result.is_err();
+        Some(result)
+    }
+}
+
+/// Iterator over the tiles before a reader's position, in reverse order. Created by `PlacedArchiveReader::rev_tiles()`.
+pub struct ReverseTiles<'r, 'a, R: Read + Seek> {
+    reader: &'r mut PlacedArchiveReader<'a, R>,
+}
+
+impl<'r, 'a, R: Read + Seek + 'a> Iterator for ReverseTiles<'r, 'a, R> {
+    type Item = DecodedTilePlacement;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.reader.previous_tile()
     }
 }
 
@@ -217,16 +936,182 @@ mod tests {
     };
 
     use chrono::NaiveDateTime;
-    use rand::Rng;
+    use rand::{Rng, SeedableRng};
     use tempfile::NamedTempFile;
 
-    use crate::{structures::StoredTilePlacement, PlacedArchiveReader};
+    use crate::{
+        constants::{BINCODE_CONFIG, FORMAT_VERSION},
+        errors::{PlacedArchiveError, ReadError, VerificationError},
+        legacy::{CanvasSizeChangeV0, ChunkDescriptionV0, MetaV0, StoredTilePlacementV0},
+        structures::{
+            BoundingBox, ChunkDescription, ChunkingStrategy, Compression, DecodedTilePlacement,
+            StoredTilePlacement, TileEncoding,
+        },
+        PlacedArchiveReader, PlacedArchiveWriterOptions,
+    };
+
+    /// Writes an archive by hand, optionally with a version file, containing the given meta and a single tile chunk.
+    fn write_raw_archive(
+        format_version: Option<u32>,
+        meta: impl bincode::Encode,
+        tiles: &[impl bincode::Encode],
+    ) -> std::fs::File {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut config = mla::config::ArchiveWriterConfig::new();
+        config.disable_layer(mla::Layers::ENCRYPT);
+        let mut mla = mla::ArchiveWriter::from_config(writeable_file, config).unwrap();
+
+        if let Some(format_version) = format_version {
+            let mut version_buf = Vec::new();
+            bincode::encode_into_std_write(format_version, &mut version_buf, BINCODE_CONFIG)
+                .unwrap();
+            mla.add_file("version", version_buf.len() as u64, version_buf.as_slice())
+                .unwrap();
+        }
+
+        let mut tile_buf = Vec::new();
+        for tile in tiles {
+            bincode::encode_into_std_write(tile, &mut tile_buf, BINCODE_CONFIG).unwrap();
+        }
+        mla.add_file("tiles/0", tile_buf.len() as u64, tile_buf.as_slice())
+            .unwrap();
+
+        let mut meta_buf = Vec::new();
+        bincode::encode_into_std_write(meta, &mut meta_buf, BINCODE_CONFIG).unwrap();
+        mla.add_file("meta", meta_buf.len() as u64, meta_buf.as_slice())
+            .unwrap();
+
+        mla.finalize().unwrap();
+
+        readable_file
+    }
+
+    fn meta_v0(num_tiles: u32) -> MetaV0 {
+        MetaV0 {
+            canvas_size_changes: vec![CanvasSizeChangeV0 {
+                width: 2000,
+                height: 2000,
+                ms_since_epoch: 0,
+            }],
+            total_tile_placements: num_tiles as u64,
+            last_tile_placed_at_ms_since_epoch: num_tiles - 1,
+            color_id_to_tuple: BTreeMap::from([(0, [0, 0, 0, 255])]),
+            chunk_descs: vec![ChunkDescriptionV0 {
+                id: 0,
+                up_to_ms_since_epoch: num_tiles - 1,
+                num_tiles,
+            }],
+        }
+    }
+
+    #[test]
+    fn reads_version_0_archive() {
+        let tiles = (0..10)
+            .map(|i| StoredTilePlacementV0 {
+                x: i,
+                y: i,
+                color_index: 0,
+                ms_since_epoch: i as u32,
+            })
+            .collect::<Vec<_>>();
+        let reader =
+            PlacedArchiveReader::new(write_raw_archive(None, meta_v0(10), &tiles)).unwrap();
+
+        assert_eq!(reader.format_version, 0);
+        assert_eq!(
+            reader.meta.chunking_strategy,
+            ChunkingStrategy::TileCount(10)
+        );
+        assert_eq!(reader.meta.compression, Compression::None);
+        assert_eq!(reader.meta.total_tile_placements, 10);
+        assert_eq!(
+            reader.map(|tile| tile.ms_since_epoch).collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn reads_current_version_archive() {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer = crate::PlacedArchiveWriter::new(writeable_file).unwrap();
+        archive_writer
+            .add_tile(
+                0,
+                0,
+                [0, 0, 0, 255],
+                NaiveDateTime::from_timestamp_millis(0).unwrap(),
+            )
+            .unwrap();
+        archive_writer.finalize().unwrap();
+
+        let reader = PlacedArchiveReader::new(readable_file).unwrap();
+        assert_eq!(reader.format_version, FORMAT_VERSION);
+    }
+
+    #[test]
+    fn rejects_future_version_archive() {
+        let file = write_raw_archive(
+            Some(FORMAT_VERSION + 1),
+            meta_v0(1),
+            &[] as &[StoredTilePlacement],
+        );
+
+        assert!(matches!(
+            PlacedArchiveReader::new(file),
+            Err(PlacedArchiveError::UnsupportedFormatVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn reads_compact_chunk_without_tile_offsets() {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer = crate::PlacedArchiveWriter::with_options(
+            writeable_file,
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(1000),
+                tile_encoding: TileEncoding::Compact,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for i in 0..1000 {
+            archive_writer
+                .add_tile(
+                    i % 100,
+                    i / 100,
+                    [0, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(i as i64 * 2).unwrap(),
+                )
+                .unwrap();
+        }
+        archive_writer.finalize().unwrap();
+
+        let mut reader = PlacedArchiveReader::new(readable_file).unwrap();
+        let tile_buf = reader.read_chunk_file(0).unwrap();
+        let tiles = reader.by_ref().collect::<Vec<_>>();
+        assert_eq!(tiles.len(), 1000);
+
+        // Archives written before compact chunks had offset indices
+        let mut reader = PlacedArchiveReader::new(write_raw_archive(
+            Some(FORMAT_VERSION),
+            reader.meta.clone(),
+            &tile_buf,
+        ))
+        .unwrap();
+        assert_eq!(reader.seek_to_ms(1200).unwrap(), 600);
+        assert_eq!(reader.previous_tile().unwrap().ms_since_epoch, 1198);
+        reader.seek_to_tile_index(0).unwrap();
+        assert_eq!(reader.collect::<Vec<_>>(), tiles);
+    }
 
     #[test]
     fn read_trait() {
         let writeable_file = NamedTempFile::new().unwrap();
         let readable_file = writeable_file.reopen().unwrap();
-        let mut archive_writer = crate::PlacedArchiveWriter::new(writeable_file);
+        let mut archive_writer = crate::PlacedArchiveWriter::new(writeable_file).unwrap();
 
         let canvas_size = 512;
         let required_num_of_tile_updates = (canvas_size as u32) * (canvas_size as u32);
@@ -248,20 +1133,22 @@ mod tests {
             let tile = StoredTilePlacement {
                 x: generator.gen_range(0..canvas_size),
                 y: generator.gen_range(0..canvas_size),
-                color_index: generator.gen_range(0..color_id_to_tuple.len() as u8),
-                ms_since_epoch: i,
+                color_index: generator.gen_range(0..color_id_to_tuple.len() as u16),
+                ms_since_epoch: i as u64,
             };
 
-            archive_writer.add_tile(
-                tile.x,
-                tile.y,
-                *color_id_to_tuple.get(&tile.color_index).unwrap(),
-                NaiveDateTime::from_timestamp_millis(tile.ms_since_epoch as i64).unwrap(),
-            );
+            archive_writer
+                .add_tile(
+                    tile.x,
+                    tile.y,
+                    *color_id_to_tuple.get(&tile.color_index).unwrap(),
+                    NaiveDateTime::from_timestamp_millis(tile.ms_since_epoch as i64).unwrap(),
+                )
+                .unwrap();
             expected_tiles.push(tile);
         }
 
-        archive_writer.finalize(false);
+        archive_writer.finalize().unwrap();
 
         let reader = PlacedArchiveReader::new(readable_file).unwrap();
         let read_tiles = reader.collect::<Vec<_>>();
@@ -278,11 +1165,192 @@ mod tests {
         }
     }
 
+    #[test]
+    fn seek_to_ms() {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer = crate::PlacedArchiveWriter::with_options(
+            writeable_file,
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(100),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        for i in 0..1000 {
+            archive_writer
+                .add_tile(
+                    0,
+                    0,
+                    [0, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(i * 2).unwrap(),
+                )
+                .unwrap();
+        }
+
+        archive_writer.finalize().unwrap();
+
+        let mut reader = PlacedArchiveReader::new(readable_file).unwrap();
+
+        assert_eq!(reader.seek_to_ms(501).unwrap(), 251);
+        assert_eq!(reader.next().unwrap().ms_since_epoch, 502);
+
+        // First tile of a chunk
+        assert_eq!(reader.seek_to_ms(200).unwrap(), 100);
+        assert_eq!(reader.next().unwrap().ms_since_epoch, 200);
+
+        // Last tile of a chunk
+        assert_eq!(reader.seek_to_ms(198).unwrap(), 99);
+        assert_eq!(reader.next().unwrap().ms_since_epoch, 198);
+        assert_eq!(reader.next().unwrap().ms_since_epoch, 200);
+
+        assert_eq!(reader.seek_to_ms(0).unwrap(), 0);
+        assert_eq!(reader.next().unwrap().ms_since_epoch, 0);
+
+        assert_eq!(reader.seek_to_ms(1_000_000).unwrap(), 1000);
+        assert!(reader.next().is_none());
+
+        reader.seek_to_tile_index(150).unwrap();
+        assert_eq!(reader.next().unwrap().ms_since_epoch, 300);
+    }
+
+    #[test]
+    fn canvas_at() {
+        let write_archive = |generate_snapshots: bool| {
+            let writeable_file = NamedTempFile::new().unwrap();
+            let readable_file = writeable_file.reopen().unwrap();
+            let mut archive_writer = crate::PlacedArchiveWriter::with_options(
+                writeable_file,
+                PlacedArchiveWriterOptions {
+                    chunking_strategy: ChunkingStrategy::TileCount(100),
+                    generate_snapshots,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            archive_writer
+                .add_canvas_size_change(32, 32, NaiveDateTime::from_timestamp_millis(0).unwrap())
+                .unwrap();
+
+            let mut generator = rand::rngs::StdRng::seed_from_u64(0);
+            for i in 0..1000 {
+                archive_writer
+                    .add_tile(
+                        generator.gen_range(0..32),
+                        generator.gen_range(0..32),
+                        [generator.gen_range(0..4) * 64, 0, 0, 255],
+                        NaiveDateTime::from_timestamp_millis(i * 2).unwrap(),
+                    )
+                    .unwrap();
+            }
+
+            archive_writer.finalize().unwrap();
+
+            PlacedArchiveReader::new(readable_file).unwrap()
+        };
+
+        let mut reader = write_archive(true);
+        let mut reader_without_snapshots = write_archive(false);
+
+        for ms_since_epoch in [0, 1, 198, 200, 201, 555, 1998, 5000] {
+            let canvas = reader.canvas_at(ms_since_epoch).unwrap();
+            let expected_canvas = reader_without_snapshots.canvas_at(ms_since_epoch).unwrap();
+            assert!(canvas == expected_canvas);
+
+            // The reader continues with the first tile placed afterwards
+            assert_eq!(
+                reader.next().map(|tile| tile.ms_since_epoch),
+                (ms_since_epoch < 1998).then_some(ms_since_epoch / 2 * 2 + 2)
+            );
+        }
+
+        let mut expected_canvas = image::RgbaImage::new(32, 32);
+        expected_canvas.fill(0xff);
+        reader_without_snapshots.seek_to_tile_index(0).unwrap();
+        for tile in reader_without_snapshots.by_ref().take(278) {
+            expected_canvas.put_pixel(tile.x as u32, tile.y as u32, image::Rgba(tile.color));
+        }
+        assert!(reader.canvas_at(555).unwrap() == expected_canvas);
+    }
+
+    #[test]
+    fn pixel_history() {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let expected_readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer = crate::PlacedArchiveWriter::with_options(
+            writeable_file,
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(100),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // Most tiles are placed in the top left corner, so most chunks can be skipped for the bottom right one
+        let mut generator = rand::rngs::StdRng::seed_from_u64(0);
+        for i in 0..1000 {
+            let (x, y) = match i % 50 {
+                0 => (generator.gen_range(100..200), generator.gen_range(100..200)),
+                _ => (generator.gen_range(0..64), generator.gen_range(0..64)),
+            };
+            archive_writer
+                .add_tile(
+                    x,
+                    y,
+                    [generator.gen_range(0..4) * 64, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(i).unwrap(),
+                )
+                .unwrap();
+        }
+        archive_writer.finalize().unwrap();
+
+        let mut reader = PlacedArchiveReader::new(readable_file).unwrap();
+        let mut expected_reader = PlacedArchiveReader::new(expected_readable_file).unwrap();
+        reader.seek_to_tile_index(123).unwrap();
+
+        for region in [
+            BoundingBox {
+                min_x: 10,
+                min_y: 20,
+                max_x: 10,
+                max_y: 20,
+            },
+            BoundingBox {
+                min_x: 30,
+                min_y: 30,
+                max_x: 150,
+                max_y: 150,
+            },
+            BoundingBox {
+                min_x: 500,
+                min_y: 500,
+                max_x: 600,
+                max_y: 600,
+            },
+        ] {
+            let history = reader.pixel_history(region).unwrap();
+            // The reader stays where it was
+            assert_eq!(
+                reader.stream_position().unwrap(),
+                123 * StoredTilePlacement::encoded_size() as u64
+            );
+
+            expected_reader.seek_to_tile_index(0).unwrap();
+            let expected_history = expected_reader
+                .by_ref()
+                .filter(|tile| region.contains(tile.x, tile.y))
+                .collect::<Vec<_>>();
+            assert_eq!(history, expected_history);
+        }
+    }
+
     #[test]
     fn seek_trait() {
         let writeable_file = NamedTempFile::new().unwrap();
         let readable_file = writeable_file.reopen().unwrap();
-        let mut archive_writer = crate::PlacedArchiveWriter::new(writeable_file);
+        let mut archive_writer = crate::PlacedArchiveWriter::new(writeable_file).unwrap();
 
         let canvas_size = 512;
 
@@ -303,20 +1371,22 @@ mod tests {
             let tile = StoredTilePlacement {
                 x: generator.gen_range(0..canvas_size),
                 y: generator.gen_range(0..canvas_size),
-                color_index: generator.gen_range(0..color_id_to_tuple.len() as u8),
-                ms_since_epoch: i,
+                color_index: generator.gen_range(0..color_id_to_tuple.len() as u16),
+                ms_since_epoch: i as u64,
             };
 
-            archive_writer.add_tile(
-                tile.x,
-                tile.y,
-                *color_id_to_tuple.get(&tile.color_index).unwrap(),
-                NaiveDateTime::from_timestamp_millis(tile.ms_since_epoch as i64).unwrap(),
-            );
+            archive_writer
+                .add_tile(
+                    tile.x,
+                    tile.y,
+                    *color_id_to_tuple.get(&tile.color_index).unwrap(),
+                    NaiveDateTime::from_timestamp_millis(tile.ms_since_epoch as i64).unwrap(),
+                )
+                .unwrap();
             expected_tiles.push(tile);
         }
 
-        archive_writer.finalize(false);
+        archive_writer.finalize().unwrap();
 
         let mut reader = PlacedArchiveReader::new(readable_file).unwrap();
 
@@ -331,7 +1401,7 @@ mod tests {
         // Offset is now 2 tiles
         assert_eq!(tile.ms_since_epoch, 1);
 
-        let current_pos = reader.seek(SeekFrom::Current(0)).unwrap();
+        let current_pos = reader.stream_position().unwrap();
         assert_eq!(current_pos, StoredTilePlacement::encoded_size() as u64 * 2);
 
         reader
@@ -350,4 +1420,205 @@ mod tests {
         // Offset is now 3 tiles
         assert_eq!(tile.ms_since_epoch, 2);
     }
+
+    #[test]
+    fn verify() {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer = crate::PlacedArchiveWriter::new(writeable_file).unwrap();
+        let mut tiles = (0..10)
+            .map(|i| StoredTilePlacement {
+                x: i,
+                y: i,
+                color_index: 0,
+                ms_since_epoch: i as u64,
+            })
+            .collect::<Vec<_>>();
+        for tile in &tiles {
+            archive_writer
+                .add_tile(
+                    tile.x,
+                    tile.y,
+                    [0, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(tile.ms_since_epoch as i64).unwrap(),
+                )
+                .unwrap();
+        }
+        archive_writer.finalize().unwrap();
+
+        let mut reader = PlacedArchiveReader::new(readable_file).unwrap();
+        assert_eq!(reader.verify(), Ok(()));
+        let mut meta = reader.meta.clone();
+        // Verifying doesn't move the reader
+        assert_eq!(reader.count(), 10);
+
+        meta.total_tile_placements = 15;
+        meta.chunk_descs.push(ChunkDescription {
+            id: 1,
+            up_to_ms_since_epoch: 14,
+            num_tiles: 5,
+            checksum: None,
+        });
+        tiles[3].ms_since_epoch = 9;
+        tiles[5].color_index = 7;
+
+        let mut reader =
+            PlacedArchiveReader::new(write_raw_archive(Some(FORMAT_VERSION), meta, &tiles))
+                .unwrap();
+        assert_eq!(
+            reader.verify(),
+            Err(vec![
+                VerificationError::ChecksumMismatch(0),
+                VerificationError::UnknownColor {
+                    chunk_id: 0,
+                    color_index: 7
+                },
+                VerificationError::UnorderedTiles(0),
+                VerificationError::MissingChunk(1),
+                VerificationError::TotalTileCountMismatch {
+                    expected: 15,
+                    found: 10
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn rev_tiles() {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer = crate::PlacedArchiveWriter::with_options(
+            writeable_file,
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(10),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for i in 0..35 {
+            archive_writer
+                .add_tile(
+                    i % 5,
+                    0,
+                    [0, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(i as i64).unwrap(),
+                )
+                .unwrap();
+        }
+        archive_writer.finalize().unwrap();
+
+        let mut reader = PlacedArchiveReader::new(readable_file).unwrap();
+        assert_eq!(reader.previous_tile(), None);
+
+        reader.seek(SeekFrom::End(0)).unwrap();
+        assert_eq!(
+            reader
+                .rev_tiles()
+                .map(|tile| tile.ms_since_epoch)
+                .collect::<Vec<_>>(),
+            (0..35).rev().collect::<Vec<_>>()
+        );
+
+        reader.seek_to_ms(20).unwrap();
+        assert_eq!(reader.previous_tile().unwrap().ms_since_epoch, 19);
+        assert_eq!(reader.next().unwrap().ms_since_epoch, 19);
+
+        // Last placement at each pixel before 17ms
+        reader.seek_to_ms(17).unwrap();
+        let mut last_placements = BTreeMap::new();
+        for tile in reader.rev_tiles() {
+            last_placements.entry(tile.x).or_insert(tile.ms_since_epoch);
+        }
+        assert_eq!(
+            last_placements.into_values().collect::<Vec<_>>(),
+            vec![15, 16, 12, 13, 14]
+        );
+    }
+
+    #[test]
+    fn try_iter() {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer = crate::PlacedArchiveWriter::new(writeable_file).unwrap();
+        let mut tiles = (0..3)
+            .map(|i| StoredTilePlacement {
+                x: i,
+                y: 0,
+                color_index: 0,
+                ms_since_epoch: i as u64,
+            })
+            .collect::<Vec<_>>();
+        for tile in &tiles {
+            archive_writer
+                .add_tile(
+                    tile.x,
+                    tile.y,
+                    [0, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(tile.ms_since_epoch as i64).unwrap(),
+                )
+                .unwrap();
+        }
+        archive_writer.finalize().unwrap();
+
+        let mut reader = PlacedArchiveReader::new(readable_file).unwrap();
+        let meta = reader.meta.clone();
+        assert!(reader.try_iter().map(Result::unwrap).eq((0..3).map(|i| {
+            DecodedTilePlacement {
+                x: i,
+                y: 0,
+                ms_since_epoch: i as u64,
+                color: [0, 0, 0, 255],
+                user_id: None,
+            }
+        })));
+
+        let mut truncated_tiles = Vec::new();
+        for tile in &tiles {
+            tile.write_into(&mut truncated_tiles);
+        }
+        truncated_tiles.truncate(truncated_tiles.len() - 4);
+        let mut reader = PlacedArchiveReader::new(write_raw_archive(
+            Some(FORMAT_VERSION),
+            meta.clone(),
+            &truncated_tiles,
+        ))
+        .unwrap();
+        let results = reader.try_iter().collect::<Vec<_>>();
+        assert_eq!(results.len(), 3);
+        assert!(matches!(results[2], Err(ReadError::TruncatedRecord(0))));
+
+        let mut meta_with_missing_chunk = meta.clone();
+        meta_with_missing_chunk.chunk_descs.push(ChunkDescription {
+            id: 1,
+            up_to_ms_since_epoch: 5,
+            num_tiles: 3,
+            checksum: None,
+        });
+        let mut reader = PlacedArchiveReader::new(write_raw_archive(
+            Some(FORMAT_VERSION),
+            meta_with_missing_chunk,
+            &tiles,
+        ))
+        .unwrap();
+        let results = reader.try_iter().collect::<Vec<_>>();
+        assert_eq!(results.len(), 4);
+        assert!(matches!(results[3], Err(ReadError::MissingChunk(1))));
+
+        tiles[1].color_index = 9;
+        let mut reader =
+            PlacedArchiveReader::new(write_raw_archive(Some(FORMAT_VERSION), meta, &tiles))
+                .unwrap();
+        let results = reader.try_iter().collect::<Vec<_>>();
+        assert_eq!(results.len(), 2);
+        assert!(matches!(
+            results[1],
+            Err(ReadError::UnknownColor {
+                chunk_id: 0,
+                color_index: 9
+            })
+        ));
+        // The plain iterator ends at the broken tile instead of panicking
+        reader.seek_to_tile_index(0).unwrap();
+        assert_eq!(reader.count(), 1);
+    }
 }
@@ -1,205 +1,1940 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fs::File,
     io::{Read, Seek, SeekFrom, Write},
 };
 
-// todo: try different compression (frame grid, encode each second in an array, even if nothing changed)
 use chrono::NaiveDateTime;
 use image::RgbImage;
 use mla::{config::ArchiveWriterConfig, ArchiveWriter};
 use tempfile::tempfile;
+use x25519_dalek::{PublicKey, StaticSecret};
 
 use crate::{
-    constants::BINCODE_CONFIG,
-    structures::{CanvasSizeChange, ChunkDescription, Meta, StoredTilePlacement},
+    constants::{BINCODE_CONFIG, COMPACT_BINCODE_CONFIG, FORMAT_VERSION},
+    errors::{PlacedArchiveError, PlacedArchiveWriteError},
+    external_sort::{spill_sorted_run, MergedRuns},
+    pixel_index::PixelIndex,
+    structures::{
+        CanvasSizeChange, ChunkDescription, ChunkFootprint, ChunkingStrategy, Compression,
+        EditShape, Meta, ModeratorEdit, StoredTilePlacement, TileEncoding,
+    },
+    tile_encoding::{encode_frame_grid, encode_tile, CompactTileIndex},
+    PlacedArchiveReader,
 };
 
-// todo: make parameter
-const NUM_CHUNKS: u32 = 64;
+#[derive(Debug, Clone)]
+pub struct PlacedArchiveWriterOptions {
+    pub chunking_strategy: ChunkingStrategy,
+    pub compression: Compression,
+    /// Maximum number of tile placements buffered in memory. Once reached, they are sorted and spilled to a temporary file.
+    pub max_tiles_in_memory: usize,
+    /// Set if tiles are added in chronological order, so they can be streamed straight into the archive without sorting.
+    pub presorted_input: bool,
+    pub tile_encoding: TileEncoding,
+    /// Store a snapshot of the canvas after each chunk.
+    pub generate_snapshots: bool,
+    /// Grow the canvas whenever a tile is placed outside of it, instead of rejecting the tile.
+    /// Growth within a single chunk is recorded as one canvas size change, starting at the first tile that didn't fit.
+    pub infer_canvas_size_changes: bool,
+    /// Encrypt the archive for these recipients, so it can only be read with one of their private keys.
+    /// The archive is left unencrypted if empty.
+    pub encryption_public_keys: Vec<PublicKey>,
+}
+
+impl Default for PlacedArchiveWriterOptions {
+    fn default() -> Self {
+        PlacedArchiveWriterOptions {
+            chunking_strategy: ChunkingStrategy::default(),
+            compression: Compression::default(),
+            max_tiles_in_memory: 10_000_000,
+            presorted_input: false,
+            tile_encoding: TileEncoding::default(),
+            generate_snapshots: false,
+            infer_canvas_size_changes: true,
+            encryption_public_keys: Vec::new(),
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Eq)]
-struct IntermediateTilePlacement {
+pub(crate) struct IntermediateTilePlacement {
     pub x: u16,
     pub y: u16,
     pub placed_at: NaiveDateTime,
-    pub color_index: u8,
+    pub color_index: u16,
+    pub user_id: Option<u32>,
+    /// Order in which the tile was added, so tiles placed at the same time keep their relative order
+    pub sequence: u64,
 }
 
 impl PartialOrd for IntermediateTilePlacement {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.placed_at.cmp(&other.placed_at))
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for IntermediateTilePlacement {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.placed_at.cmp(&other.placed_at)
+        self.placed_at
+            .cmp(&other.placed_at)
+            .then(self.sequence.cmp(&other.sequence))
     }
 }
 
+struct CurrentChunk {
+    tile_buf: Vec<u8>,
+    num_tiles: u32,
+    first_tile_placed_at: NaiveDateTime,
+    last_tile_placed_at: NaiveDateTime,
+    // Pixels placed in this chunk as (y, x), for its footprint
+    touched_pixels: BTreeSet<(u16, u16)>,
+    // Placer of each tile as a variable-length user id plus one, with zero for unknown placers
+    user_buf: Vec<u8>,
+    has_users: bool,
+    // Offset index written next to `TileEncoding::Compact` chunks
+    compact_tile_index: CompactTileIndex,
+}
+
 pub struct PlacedArchiveWriter<'a, W: Write> {
     mla: ArchiveWriter<'a, W>,
-    color_tuple_to_id: BTreeMap<[u8; 4], u8>,
+    options: PlacedArchiveWriterOptions,
+    color_tuple_to_id: BTreeMap<[u8; 4], u16>,
+    color_id_to_tuple: Vec<[u8; 4]>,
+    user_hash_to_id: BTreeMap<String, u32>,
+    canvas_size_changes: Vec<CanvasSizeChange>,
+    // Explicit canvas size changes (width, height) that take effect once a tile placed at or after them is written
+    pending_canvas_size_changes: VecDeque<(NaiveDateTime, u16, u16)>,
+    // Moderator edits (placed at, shape, color index) in the order they were added
+    moderator_edits: Vec<(NaiveDateTime, EditShape, u16)>,
+    // Chunk in which the last canvas size change was inferred, so further growth in that chunk can be merged into it
+    last_inferred_canvas_size_change_chunk_id: Option<u32>,
+    num_tiles_added: u64,
+
+    // Unsorted input waiting to be sorted
     tile_placements: Vec<IntermediateTilePlacement>,
+    spilled_runs: Vec<(File, u64)>,
+
+    // Sorted tiles that have been written (or are about to be written) to the archive
+    first_tile_placed_at: Option<NaiveDateTime>,
+    last_tile_placed_at: Option<NaiveDateTime>,
+    current_chunk: Option<CurrentChunk>,
+    chunk_descs: Vec<ChunkDescription>,
+    pixel_index: PixelIndex,
+    snapshot_canvas: Option<RgbImage>,
 }
 
 impl<'a, W: Write> PlacedArchiveWriter<'a, W> {
-    pub fn new(dest: W) -> Self {
+    pub fn new(dest: W) -> Result<Self, PlacedArchiveWriteError> {
+        Self::with_options(dest, PlacedArchiveWriterOptions::default())
+    }
+
+    pub fn with_options(
+        dest: W,
+        options: PlacedArchiveWriterOptions,
+    ) -> Result<Self, PlacedArchiveWriteError> {
         let mut config = ArchiveWriterConfig::new();
-        config.disable_layer(mla::Layers::ENCRYPT);
-        // todo: enable compression?
-        let mla = ArchiveWriter::from_config(dest, config).unwrap();
+        if !options.encryption_public_keys.is_empty() {
+            config
+                .enable_layer(mla::Layers::ENCRYPT)
+                .add_public_keys(&options.encryption_public_keys);
+        }
+        if let Compression::Brotli(level) = options.compression {
+            config
+                .enable_layer(mla::Layers::COMPRESS)
+                .with_compression_level(level)
+                .map_err(mla::errors::Error::from)?;
+        }
+        let mut mla = ArchiveWriter::from_config(dest, config)?;
 
-        PlacedArchiveWriter {
+        let mut version_buf = Vec::new();
+        bincode::encode_into_std_write(FORMAT_VERSION, &mut version_buf, BINCODE_CONFIG)?;
+        mla.add_file("version", version_buf.len() as u64, version_buf.as_slice())?;
+
+        let snapshot_canvas = if options.generate_snapshots {
+            Some(RgbImage::new(0, 0))
+        } else {
+            None
+        };
+
+        Ok(PlacedArchiveWriter {
             mla,
+            options,
             color_tuple_to_id: BTreeMap::new(),
+            color_id_to_tuple: Vec::new(),
+            user_hash_to_id: BTreeMap::new(),
+            canvas_size_changes: Vec::new(),
+            pending_canvas_size_changes: VecDeque::new(),
+            moderator_edits: Vec::new(),
+            last_inferred_canvas_size_change_chunk_id: None,
+            num_tiles_added: 0,
             tile_placements: Vec::new(),
+            spilled_runs: Vec::new(),
+            first_tile_placed_at: None,
+            last_tile_placed_at: None,
+            current_chunk: None,
+            chunk_descs: Vec::new(),
+            pixel_index: PixelIndex::default(),
+            snapshot_canvas,
+        })
+    }
+
+    /// Continues the archive read from `existing`, writing it with the tiles added afterwards to `dest`.
+    /// The chunks and snapshots of the existing archive are copied as they are, so tiles can't be placed before its last one.
+    /// The existing chunking strategy, compression and tile encoding are kept, overriding the ones in `options`.
+    /// Only archives written with the current `FORMAT_VERSION` can be continued, older ones have to be packed again.
+    pub fn append<R: Read + Seek>(
+        existing: R,
+        dest: W,
+        options: PlacedArchiveWriterOptions,
+    ) -> Result<Self, PlacedArchiveWriteError> {
+        let reader = PlacedArchiveReader::new(existing)
+            .map_err(PlacedArchiveWriteError::CouldNotReadArchive)?;
+
+        Self::continue_archive(reader, dest, options)
+    }
+
+    /// Like `append`, but the existing archive may be encrypted for any of the recipients owning `private_keys`.
+    /// The result is encrypted for `options.encryption_public_keys`, which don't have to match the existing archive's recipients.
+    pub fn append_with_private_keys<R: Read + Seek>(
+        existing: R,
+        private_keys: &[StaticSecret],
+        dest: W,
+        options: PlacedArchiveWriterOptions,
+    ) -> Result<Self, PlacedArchiveWriteError> {
+        let reader = PlacedArchiveReader::with_private_keys(existing, private_keys)
+            .map_err(PlacedArchiveWriteError::CouldNotReadArchive)?;
+
+        Self::continue_archive(reader, dest, options)
+    }
+
+    fn continue_archive<'r, R: Read + Seek + 'r>(
+        mut reader: PlacedArchiveReader<'r, R>,
+        dest: W,
+        options: PlacedArchiveWriterOptions,
+    ) -> Result<Self, PlacedArchiveWriteError> {
+        // Chunks are copied without being converted to the current layout
+        if reader.format_version != FORMAT_VERSION {
+            return Err(PlacedArchiveWriteError::CouldNotReadArchive(
+                PlacedArchiveError::UnsupportedFormatVersion(reader.format_version),
+            ));
+        }
+
+        let meta = reader.meta.clone();
+        let mut writer = Self::with_options(
+            dest,
+            PlacedArchiveWriterOptions {
+                chunking_strategy: meta.chunking_strategy,
+                compression: meta.compression,
+                tile_encoding: meta.tile_encoding,
+                ..options
+            },
+        )?;
+
+        // Everything else is rewritten when the archive is finalized
+        let file_names = reader
+            .mla
+            .list_files()?
+            .filter(|file_name| {
+                file_name.starts_with("tiles/")
+                    || file_name.starts_with("tile_offsets/")
+                    || file_name.starts_with("footprints/")
+                    || file_name.starts_with("snapshots/")
+                    || file_name.starts_with("users/")
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for file_name in file_names {
+            if let Some(file) = reader.mla.get_file(file_name.clone())? {
+                writer.mla.add_file(&file_name, file.size, file.data)?;
+            }
+        }
+
+        let first_tile_placed_at = meta.first_tile_placed_at().unwrap();
+        let placed_at = |ms_since_epoch: u64| {
+            first_tile_placed_at + chrono::Duration::milliseconds(ms_since_epoch as i64)
+        };
+
+        for (color_index, color) in &meta.color_id_to_tuple {
+            writer.color_tuple_to_id.insert(*color, *color_index);
+            writer.color_id_to_tuple.push(*color);
+        }
+        if let Some(user_table) = reader
+            .user_table()
+            .map_err(PlacedArchiveWriteError::CouldNotReadArchive)?
+        {
+            writer.user_hash_to_id = user_table
+                .into_iter()
+                .enumerate()
+                .map(|(user_id, user_hash)| (user_hash, user_id as u32))
+                .collect();
+        }
+        writer.canvas_size_changes = meta.canvas_size_changes.clone();
+        writer.moderator_edits = meta
+            .moderator_edits
+            .iter()
+            .map(|edit| (placed_at(edit.ms_since_epoch), edit.shape, edit.color_index))
+            .collect();
+        writer.num_tiles_added = meta.total_tile_placements;
+        writer.first_tile_placed_at = Some(first_tile_placed_at);
+        writer.last_tile_placed_at = Some(placed_at(meta.last_tile_placed_at_ms_since_epoch));
+
+        for chunk_desc in &meta.chunk_descs {
+            let footprint = reader
+                .chunk_footprint(chunk_desc.id)
+                .map_err(PlacedArchiveWriteError::CouldNotReadArchive)?;
+            if let Some(footprint) = footprint {
+                for (x, y) in footprint.pixels() {
+                    writer.pixel_index.insert(x, y, chunk_desc.id);
+                }
+            }
+        }
+        writer.chunk_descs = meta.chunk_descs;
+
+        if writer.snapshot_canvas.is_some() {
+            let canvas = reader
+                .canvas_at(meta.last_tile_placed_at_ms_since_epoch)
+                .map_err(PlacedArchiveWriteError::CouldNotReconstructCanvas)?;
+            writer.snapshot_canvas = Some(image::DynamicImage::ImageRgba8(canvas).into_rgb8());
+        }
+
+        Ok(writer)
+    }
+
+    pub fn add_tile(
+        &mut self,
+        x: u16,
+        y: u16,
+        color: [u8; 4],
+        placed_at: NaiveDateTime,
+    ) -> Result<(), PlacedArchiveWriteError> {
+        self.add_tile_with_user_id(x, y, color, placed_at, None)
+    }
+
+    /// Adds a tile like `add_tile`, and records who placed it in the archive's user table.
+    /// `user_hash` identifies the placer, e.g. the hashed user id of the r/place datasets.
+    /// Placers aren't recorded in frame-grid chunks, which only keep one placement per pixel and frame.
+    pub fn add_tile_placed_by(
+        &mut self,
+        x: u16,
+        y: u16,
+        color: [u8; 4],
+        placed_at: NaiveDateTime,
+        user_hash: &str,
+    ) -> Result<(), PlacedArchiveWriteError> {
+        let user_id = match self.user_hash_to_id.get(user_hash) {
+            Some(user_id) => *user_id,
+            None => {
+                let user_id = self.user_hash_to_id.len() as u32;
+                self.user_hash_to_id.insert(user_hash.to_string(), user_id);
+                user_id
+            }
+        };
+
+        self.add_tile_with_user_id(x, y, color, placed_at, Some(user_id))
+    }
+
+    /// Records a moderator filling `shape` with `color`, and adds a tile for every pixel within it.
+    pub fn add_moderator_edit(
+        &mut self,
+        shape: EditShape,
+        color: [u8; 4],
+        placed_at: NaiveDateTime,
+    ) -> Result<(), PlacedArchiveWriteError> {
+        for (x, y) in shape.pixels() {
+            self.add_tile_with_user_id(x, y, color, placed_at, None)?;
         }
+
+        let color_index = self.get_color_index(color)?;
+        self.moderator_edits.push((placed_at, shape, color_index));
+
+        Ok(())
     }
 
-    pub fn add_tile(&mut self, x: u16, y: u16, color: [u8; 4], placed_at: NaiveDateTime) {
-        let color_map_len = self.color_tuple_to_id.len() as u8;
-        let color_index = self
-            .color_tuple_to_id
-            .entry(color)
-            .or_insert_with(|| color_map_len);
+    fn add_tile_with_user_id(
+        &mut self,
+        x: u16,
+        y: u16,
+        color: [u8; 4],
+        placed_at: NaiveDateTime,
+        user_id: Option<u32>,
+    ) -> Result<(), PlacedArchiveWriteError> {
+        // Only set before `finalize()` if the input is presorted, or an existing archive is appended to
+        if let Some(last_tile_placed_at) = self.last_tile_placed_at {
+            if placed_at < last_tile_placed_at {
+                return Err(PlacedArchiveWriteError::UnsortedInput);
+            }
+        }
+
+        let color_index = self.get_color_index(color)?;
 
-        self.tile_placements.push(IntermediateTilePlacement {
+        let tile = IntermediateTilePlacement {
             x,
             y,
             placed_at,
-            color_index: *color_index,
-        });
+            color_index,
+            user_id,
+            sequence: self.num_tiles_added,
+        };
+        self.num_tiles_added += 1;
+
+        if self.options.presorted_input {
+            return self.write_sorted_tile(tile);
+        }
+
+        self.tile_placements.push(tile);
+
+        if self.tile_placements.len() >= self.options.max_tiles_in_memory {
+            let num_tiles = self.tile_placements.len() as u64;
+            let run = spill_sorted_run(&mut self.tile_placements)?;
+            self.spilled_runs.push((run, num_tiles));
+        }
+
+        Ok(())
     }
 
-    pub fn finalize(&mut self, should_generate_snapshots: bool) {
-        self.tile_placements.sort();
+    fn get_color_index(&mut self, color: [u8; 4]) -> Result<u16, PlacedArchiveWriteError> {
+        if let Some(color_index) = self.color_tuple_to_id.get(&color) {
+            return Ok(*color_index);
+        }
 
-        let first_tile_placed_at = self.tile_placements.first().unwrap().placed_at;
-        let num_of_tiles_in_chunk = self.tile_placements.len() as u32 / NUM_CHUNKS;
+        let color_index = u16::try_from(self.color_id_to_tuple.len())
+            .ok()
+            .filter(|color_index| *color_index != StoredTilePlacement::PADDING_COLOR_INDEX)
+            .ok_or(PlacedArchiveWriteError::ColorTableOverflow)?;
+        self.color_tuple_to_id.insert(color, color_index);
+        self.color_id_to_tuple.push(color);
 
-        let mut chunk_descs: Vec<ChunkDescription> = Vec::new();
+        Ok(color_index)
+    }
 
-        for (i, tiles) in self
-            .tile_placements
-            .chunks(num_of_tiles_in_chunk as usize)
-            .enumerate()
-        {
-            let mut tile_buf = Vec::new();
+    /// Resizes the canvas to `width` x `height` starting at `changed_at`.
+    /// Changes made before the first tile are recorded as happening at the start of the archive.
+    pub fn add_canvas_size_change(
+        &mut self,
+        width: u16,
+        height: u16,
+        changed_at: NaiveDateTime,
+    ) -> Result<(), PlacedArchiveWriteError> {
+        if let Some(last_tile_placed_at) = self.last_tile_placed_at {
+            if changed_at < last_tile_placed_at {
+                return Err(PlacedArchiveWriteError::UnsortedInput);
+            }
+        }
+
+        let index = self
+            .pending_canvas_size_changes
+            .partition_point(|(pending_changed_at, _, _)| *pending_changed_at <= changed_at);
+        self.pending_canvas_size_changes
+            .insert(index, (changed_at, width, height));
+
+        Ok(())
+    }
+
+    pub fn finalize(&mut self) -> Result<(), PlacedArchiveWriteError> {
+        if self.num_tiles_added == 0 {
+            return Err(PlacedArchiveWriteError::EmptyArchive);
+        }
+
+        let sorted_tiles = MergedRuns::new(
+            std::mem::take(&mut self.spilled_runs),
+            std::mem::take(&mut self.tile_placements),
+        )?;
+        for tile in sorted_tiles {
+            self.write_sorted_tile(tile?)?;
+        }
+        self.flush_current_chunk()?;
+
+        let first_tile_placed_at = self.first_tile_placed_at.unwrap();
+
+        // Changes after the last tile don't affect any snapshots, but are still part of the canvas history
+        while let Some((changed_at, width, height)) = self.pending_canvas_size_changes.pop_front() {
+            self.set_canvas_size(
+                width,
+                height,
+                ms_since(first_tile_placed_at, changed_at.max(first_tile_placed_at)),
+            );
+        }
+
+        self.moderator_edits
+            .sort_by_key(|(placed_at, _, _)| *placed_at);
+
+        let meta = Meta {
+            first_tile_placed_at_ms_since_unix_epoch: Some(first_tile_placed_at.timestamp_millis()),
+            canvas_size_changes: self.canvas_size_changes.clone(),
+            chunking_strategy: self.options.chunking_strategy,
+            compression: self.options.compression,
+            tile_encoding: self.options.tile_encoding,
+            chunk_descs: self.chunk_descs.clone(),
+            moderator_edits: self
+                .moderator_edits
+                .iter()
+                .map(|(placed_at, shape, color_index)| ModeratorEdit {
+                    ms_since_epoch: ms_since(first_tile_placed_at, *placed_at),
+                    shape: *shape,
+                    color_index: *color_index,
+                })
+                .collect(),
+            num_users: match self.user_hash_to_id.len() {
+                0 => None,
+                num_users => Some(num_users as u32),
+            },
+            last_tile_placed_at_ms_since_epoch: ms_since(
+                first_tile_placed_at,
+                self.last_tile_placed_at.unwrap(),
+            ),
+            total_tile_placements: self
+                .chunk_descs
+                .iter()
+                .map(|chunk_desc| chunk_desc.num_tiles as u64)
+                .sum(),
+            color_id_to_tuple: BTreeMap::from_iter(
+                self.color_id_to_tuple
+                    .iter()
+                    .enumerate()
+                    .map(|(id, color)| (id as u16, *color)),
+            ),
+        };
+
+        let mut meta_buf = Vec::new();
+        bincode::encode_into_std_write(meta, &mut meta_buf, BINCODE_CONFIG)?;
+        self.mla
+            .add_file("meta", meta_buf.len() as u64, meta_buf.as_slice())?;
+
+        if !self.user_hash_to_id.is_empty() {
+            let mut user_table = vec![""; self.user_hash_to_id.len()];
+            for (user_hash, user_id) in &self.user_hash_to_id {
+                user_table[*user_id as usize] = user_hash;
+            }
+
+            let mut user_table_buf = Vec::new();
+            bincode::encode_into_std_write(user_table, &mut user_table_buf, BINCODE_CONFIG)?;
+            self.mla.add_file(
+                "users",
+                user_table_buf.len() as u64,
+                user_table_buf.as_slice(),
+            )?;
+        }
+
+        let mut pixel_index_buf = Vec::new();
+        bincode::encode_into_std_write(&self.pixel_index, &mut pixel_index_buf, BINCODE_CONFIG)?;
+        self.mla.add_file(
+            "pixel_index",
+            pixel_index_buf.len() as u64,
+            pixel_index_buf.as_slice(),
+        )?;
+
+        self.mla.finalize()?;
+
+        Ok(())
+    }
+
+    /// Appends a tile to the current chunk. Tiles must be passed in sorted order.
+    fn write_sorted_tile(
+        &mut self,
+        tile: IntermediateTilePlacement,
+    ) -> Result<(), PlacedArchiveWriteError> {
+        let first_tile_placed_at = *self.first_tile_placed_at.get_or_insert(tile.placed_at);
+
+        if let Some(current_chunk) = &self.current_chunk {
+            if should_start_new_chunk(
+                self.options.chunking_strategy,
+                self.options.tile_encoding,
+                current_chunk,
+                first_tile_placed_at,
+                tile.placed_at,
+            ) {
+                self.flush_current_chunk()?;
+            }
+        }
+
+        while let Some((changed_at, width, height)) = self.pending_canvas_size_changes.front() {
+            if *changed_at > tile.placed_at {
+                break;
+            }
 
-            for tile in tiles {
-                bincode::encode_into_std_write(
-                    StoredTilePlacement {
+            let ms_since_epoch = self.stored_ms_since_epoch(ms_since(
+                first_tile_placed_at,
+                (*changed_at).max(first_tile_placed_at),
+            ));
+            self.set_canvas_size(*width, *height, ms_since_epoch);
+            self.pending_canvas_size_changes.pop_front();
+        }
+
+        let (width, height) = self.current_canvas_size();
+        if tile.x >= width || tile.y >= height {
+            if !self.options.infer_canvas_size_changes {
+                return Err(PlacedArchiveWriteError::TileOutOfBounds {
+                    x: tile.x,
+                    y: tile.y,
+                });
+            }
+
+            let chunk_id = self.chunk_descs.len() as u32;
+            let (width, height) = match (
+                u16::try_from((width as u32).max(tile.x as u32 + 1)),
+                u16::try_from((height as u32).max(tile.y as u32 + 1)),
+            ) {
+                (Ok(width), Ok(height)) => (width, height),
+                _ => {
+                    return Err(PlacedArchiveWriteError::CanvasTooLarge {
                         x: tile.x,
                         y: tile.y,
-                        ms_since_epoch: tile
-                            .placed_at
-                            .signed_duration_since(first_tile_placed_at)
-                            .num_milliseconds() as u32,
-                        color_index: tile.color_index,
-                    },
-                    &mut tile_buf,
-                    BINCODE_CONFIG,
+                    })
+                }
+            };
+
+            match self.canvas_size_changes.last_mut() {
+                Some(last_change)
+                    if self.last_inferred_canvas_size_change_chunk_id == Some(chunk_id) =>
+                {
+                    last_change.width = width;
+                    last_change.height = height;
+                }
+                _ => self.set_canvas_size(
+                    width,
+                    height,
+                    self.stored_ms_since_epoch(ms_since(first_tile_placed_at, tile.placed_at)),
+                ),
+            }
+            self.last_inferred_canvas_size_change_chunk_id = Some(chunk_id);
+        }
+
+        let current_chunk = self.current_chunk.get_or_insert_with(|| CurrentChunk {
+            tile_buf: Vec::new(),
+            num_tiles: 0,
+            first_tile_placed_at: tile.placed_at,
+            last_tile_placed_at: tile.placed_at,
+            touched_pixels: BTreeSet::new(),
+            user_buf: Vec::new(),
+            has_users: false,
+            compact_tile_index: CompactTileIndex::default(),
+        });
+
+        let previous_ms_since_epoch = match current_chunk.num_tiles {
+            0 => 0,
+            _ => ms_since(first_tile_placed_at, current_chunk.last_tile_placed_at),
+        };
+        if self.options.tile_encoding == TileEncoding::Compact {
+            current_chunk
+                .compact_tile_index
+                .push(current_chunk.tile_buf.len(), previous_ms_since_epoch);
+        }
+        encode_tile(
+            self.options.tile_encoding,
+            StoredTilePlacement {
+                x: tile.x,
+                y: tile.y,
+                ms_since_epoch: ms_since(first_tile_placed_at, tile.placed_at),
+                color_index: tile.color_index,
+            },
+            previous_ms_since_epoch,
+            &mut current_chunk.tile_buf,
+        )?;
+        current_chunk.num_tiles += 1;
+        current_chunk.last_tile_placed_at = tile.placed_at;
+        current_chunk.touched_pixels.insert((tile.y, tile.x));
+        bincode::encode_into_std_write(
+            tile.user_id.map_or(0, |user_id| user_id + 1),
+            &mut current_chunk.user_buf,
+            COMPACT_BINCODE_CONFIG,
+        )?;
+        current_chunk.has_users |= tile.user_id.is_some();
+        self.pixel_index
+            .insert(tile.x, tile.y, self.chunk_descs.len() as u32);
+
+        self.last_tile_placed_at = Some(tile.placed_at);
+
+        self.resize_snapshot_canvas();
+        if let Some(canvas) = &mut self.snapshot_canvas {
+            canvas.put_pixel(
+                tile.x as u32,
+                tile.y as u32,
+                image::Rgb(
+                    self.color_id_to_tuple[tile.color_index as usize][0..3]
+                        .try_into()
+                        .unwrap(),
+                ),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Writes the current chunk (and a snapshot of the canvas, if enabled) to the archive.
+    fn flush_current_chunk(&mut self) -> Result<(), PlacedArchiveWriteError> {
+        let current_chunk = match self.current_chunk.take() {
+            Some(current_chunk) => current_chunk,
+            None => return Ok(()),
+        };
+
+        let id = self.chunk_descs.len() as u32;
+        let footprint = ChunkFootprint::from_touched_pixels(&current_chunk.touched_pixels);
+
+        let (tile_buf, num_tiles, up_to_ms_since_epoch) = match self.options.tile_encoding {
+            TileEncoding::FrameGrid { frame_ms } => {
+                encode_frame_grid(&current_chunk.tile_buf, frame_ms)?
+            }
+            _ => (
+                current_chunk.tile_buf,
+                current_chunk.num_tiles,
+                ms_since(
+                    self.first_tile_placed_at.unwrap(),
+                    current_chunk.last_tile_placed_at,
+                ),
+            ),
+        };
+
+        self.mla.add_file(
+            format!("tiles/{}", id).as_str(),
+            tile_buf.len() as u64,
+            tile_buf.as_slice(),
+        )?;
+
+        if self.options.tile_encoding == TileEncoding::Compact {
+            let index_buf =
+                bincode::encode_to_vec(&current_chunk.compact_tile_index, COMPACT_BINCODE_CONFIG)?;
+            self.mla.add_file(
+                format!("tile_offsets/{}", id).as_str(),
+                index_buf.len() as u64,
+                index_buf.as_slice(),
+            )?;
+        }
+
+        if let Some(footprint) = footprint {
+            let mut footprint_buf = Vec::new();
+            bincode::encode_into_std_write(footprint, &mut footprint_buf, BINCODE_CONFIG)?;
+            self.mla.add_file(
+                format!("footprints/{}", id).as_str(),
+                footprint_buf.len() as u64,
+                footprint_buf.as_slice(),
+            )?;
+        }
+
+        let is_frame_grid = matches!(self.options.tile_encoding, TileEncoding::FrameGrid { .. });
+        if current_chunk.has_users && !is_frame_grid {
+            self.mla.add_file(
+                format!("users/{}", id).as_str(),
+                current_chunk.user_buf.len() as u64,
+                current_chunk.user_buf.as_slice(),
+            )?;
+        }
+
+        self.chunk_descs.push(ChunkDescription {
+            id,
+            up_to_ms_since_epoch,
+            num_tiles,
+            checksum: Some(crc32fast::hash(&tile_buf)),
+        });
+
+        self.resize_snapshot_canvas();
+        if let Some(canvas) = &self.snapshot_canvas {
+            let mut temp_snapshot = tempfile()?;
+            let mut buf = Vec::new();
+            // needs a seekable writer
+            canvas.write_to(&mut temp_snapshot, image::ImageOutputFormat::Png)?;
+            temp_snapshot.seek(SeekFrom::Start(0))?;
+            temp_snapshot.read_to_end(&mut buf)?;
+
+            self.mla.add_file(
+                format!("snapshots/{}", id).as_str(),
+                buf.len() as u64,
+                buf.as_slice(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the time a tile placed at `ms_since_epoch` is stored at, which is the start of its frame for frame-grid chunks.
+    fn stored_ms_since_epoch(&self, ms_since_epoch: u64) -> u64 {
+        match self.options.tile_encoding {
+            TileEncoding::FrameGrid { frame_ms } => {
+                ms_since_epoch - ms_since_epoch % frame_ms.get() as u64
+            }
+            _ => ms_since_epoch,
+        }
+    }
+
+    /// Records a canvas size change. A change at the same time as the previous one replaces it.
+    fn set_canvas_size(&mut self, width: u16, height: u16, ms_since_epoch: u64) {
+        let change = CanvasSizeChange {
+            width,
+            height,
+            ms_since_epoch,
+        };
+
+        match self.canvas_size_changes.last_mut() {
+            Some(last_change) if last_change.ms_since_epoch == ms_since_epoch => {
+                *last_change = change;
+            }
+            _ => self.canvas_size_changes.push(change),
+        }
+        self.last_inferred_canvas_size_change_chunk_id = None;
+    }
+
+    fn current_canvas_size(&self) -> (u16, u16) {
+        match self.canvas_size_changes.last() {
+            Some(change) => (change.width, change.height),
+            None => (0, 0),
+        }
+    }
+
+    /// Grows (or crops) the snapshot canvas to the current canvas size, filling new space with white.
+    fn resize_snapshot_canvas(&mut self) {
+        let (width, height) = self.current_canvas_size();
+
+        if let Some(canvas) = &mut self.snapshot_canvas {
+            if canvas.dimensions() == (width as u32, height as u32) {
+                return;
+            }
+
+            let mut resized_canvas = RgbImage::new(width as u32, height as u32);
+            resized_canvas.fill(0xff);
+            image::imageops::replace(&mut resized_canvas, canvas, 0, 0);
+            *canvas = resized_canvas;
+        }
+    }
+}
+
+fn ms_since(first_tile_placed_at: NaiveDateTime, placed_at: NaiveDateTime) -> u64 {
+    placed_at
+        .signed_duration_since(first_tile_placed_at)
+        .num_milliseconds() as u64
+}
+
+/// Returns true if a tile placed at `placed_at` should not be added to `current_chunk` under the given chunking strategy.
+fn should_start_new_chunk(
+    chunking_strategy: ChunkingStrategy,
+    tile_encoding: TileEncoding,
+    current_chunk: &CurrentChunk,
+    first_tile_placed_at: NaiveDateTime,
+    placed_at: NaiveDateTime,
+) -> bool {
+    // Frame-grid chunks only keep the last placement of each cell within a frame, so a frame is never split across chunks
+    if let TileEncoding::FrameGrid { frame_ms } = tile_encoding {
+        let frame = |placed_at| ms_since(first_tile_placed_at, placed_at) / frame_ms.get() as u64;
+        if frame(placed_at) == frame(current_chunk.last_tile_placed_at) {
+            return false;
+        }
+    }
+
+    match chunking_strategy {
+        ChunkingStrategy::TileCount(max_tiles) => current_chunk.num_tiles >= max_tiles,
+        ChunkingStrategy::MaxBytes(max_bytes) => {
+            (current_chunk.tile_buf.len() + tile_encoding.max_encoded_size()) as u64 > max_bytes
+        }
+        ChunkingStrategy::TimeWindow(window_ms) => {
+            placed_at
+                .signed_duration_since(current_chunk.first_tile_placed_at)
+                .num_milliseconds()
+                >= window_ms as i64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, num::NonZeroU32, ops::Range};
+
+    use chrono::NaiveDateTime;
+    use tempfile::NamedTempFile;
+
+    use crate::{
+        constants::BINCODE_CONFIG,
+        errors::PlacedArchiveWriteError,
+        structures::{
+            BoundingBox, CanvasSizeChange, ChunkingStrategy, Compression, EditShape, ModeratorEdit,
+            StoredTilePlacement, TileEncoding,
+        },
+        PlacedArchiveReader, PlacedArchiveWriter, PlacedArchiveWriterOptions, PublicKey,
+        StaticSecret,
+    };
+
+    fn write_archive(
+        tile_timestamps: impl Iterator<Item = i64>,
+        options: PlacedArchiveWriterOptions,
+    ) -> PlacedArchiveReader<'static, File> {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer =
+            PlacedArchiveWriter::with_options(writeable_file, options).unwrap();
+
+        for (i, ms) in tile_timestamps.enumerate() {
+            archive_writer
+                .add_tile(
+                    (i % 1000) as u16,
+                    (i / 1000) as u16,
+                    [0, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(ms).unwrap(),
+                )
+                .unwrap();
+        }
+
+        archive_writer.finalize().unwrap();
+
+        PlacedArchiveReader::new(readable_file).unwrap()
+    }
+
+    fn with_chunking_strategy(chunking_strategy: ChunkingStrategy) -> PlacedArchiveWriterOptions {
+        PlacedArchiveWriterOptions {
+            chunking_strategy,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn chunk_by_tile_count() {
+        let reader = write_archive(
+            0..1000,
+            with_chunking_strategy(ChunkingStrategy::TileCount(100)),
+        );
+
+        assert_eq!(
+            reader.meta.chunking_strategy,
+            ChunkingStrategy::TileCount(100)
+        );
+        assert_eq!(reader.meta.chunk_descs.len(), 10);
+        assert!(reader.meta.chunk_descs.iter().all(|c| c.num_tiles == 100));
+        assert_eq!(reader.count(), 1000);
+    }
+
+    #[test]
+    fn chunk_by_max_bytes() {
+        let max_bytes = StoredTilePlacement::encoded_size() as u64 * 10 + 5;
+        let reader = write_archive(
+            0..1000,
+            with_chunking_strategy(ChunkingStrategy::MaxBytes(max_bytes)),
+        );
+
+        assert_eq!(reader.meta.chunk_descs.len(), 100);
+        assert!(reader.meta.chunk_descs.iter().all(|c| c.num_tiles == 10));
+        assert_eq!(reader.count(), 1000);
+    }
+
+    #[test]
+    fn chunk_by_time_window() {
+        let reader = write_archive(
+            (0..1000).map(|i| i * 10),
+            with_chunking_strategy(ChunkingStrategy::TimeWindow(1000)),
+        );
+
+        assert_eq!(reader.meta.chunk_descs.len(), 10);
+        for (i, chunk_desc) in reader.meta.chunk_descs.iter().enumerate() {
+            assert_eq!(chunk_desc.num_tiles, 100);
+            assert_eq!(chunk_desc.up_to_ms_since_epoch, i as u64 * 1000 + 990);
+        }
+        assert_eq!(reader.count(), 1000);
+    }
+
+    #[test]
+    fn fewer_tiles_than_chunk_size() {
+        let reader = write_archive(0..10, PlacedArchiveWriterOptions::default());
+
+        assert_eq!(reader.meta.chunk_descs.len(), 1);
+        assert_eq!(reader.count(), 10);
+    }
+
+    #[test]
+    fn spills_sorted_runs() {
+        // Every timestamp is used 10 times, in an order that requires sorting
+        let reader = write_archive(
+            (0..1000).map(|i| (i * 37) % 100),
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(100),
+                max_tiles_in_memory: 64,
+                ..Default::default()
+            },
+        );
+
+        let mut expected_tiles: Vec<(u16, u64)> = (0..1000)
+            .map(|i| (i as u16, ((i * 37) % 100) as u64))
+            .collect();
+        // Stable, so tiles placed at the same time keep their insertion order
+        expected_tiles.sort_by_key(|(_, ms)| *ms);
+
+        let read_tiles: Vec<(u16, u64)> = reader.map(|t| (t.x, t.ms_since_epoch)).collect();
+        assert_eq!(read_tiles, expected_tiles);
+    }
+
+    #[test]
+    fn streams_presorted_input() {
+        let reader = write_archive(
+            (0..1000).map(|i| i / 10),
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(100),
+                presorted_input: true,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(reader.meta.chunk_descs.len(), 10);
+        let read_tiles: Vec<(u16, u64)> = reader.map(|t| (t.x, t.ms_since_epoch)).collect();
+        let expected_tiles: Vec<(u16, u64)> =
+            (0..1000).map(|i| (i as u16, i as u64 / 10)).collect();
+        assert_eq!(read_tiles, expected_tiles);
+    }
+
+    #[test]
+    fn compression() {
+        let write = |compression| {
+            let writeable_file = NamedTempFile::new().unwrap();
+            let readable_file = writeable_file.reopen().unwrap();
+            let mut archive_writer = PlacedArchiveWriter::with_options(
+                writeable_file,
+                PlacedArchiveWriterOptions {
+                    compression,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            for i in 0..10_000 {
+                archive_writer
+                    .add_tile(
+                        (i % 100) as u16,
+                        (i / 100) as u16,
+                        [0, 0, 0, 255],
+                        NaiveDateTime::from_timestamp_millis(i).unwrap(),
+                    )
+                    .unwrap();
+            }
+            archive_writer.finalize().unwrap();
+
+            let archive_size = readable_file.metadata().unwrap().len();
+            (
+                PlacedArchiveReader::new(readable_file).unwrap(),
+                archive_size,
+            )
+        };
+
+        let (uncompressed_reader, uncompressed_size) = write(Compression::None);
+        let (compressed_reader, compressed_size) = write(Compression::Brotli(5));
+
+        assert_eq!(uncompressed_reader.meta.compression, Compression::None);
+        assert_eq!(compressed_reader.meta.compression, Compression::Brotli(5));
+        assert!(compressed_size < uncompressed_size / 2);
+        assert!(uncompressed_reader.eq(compressed_reader));
+    }
+
+    #[test]
+    fn rejects_unsorted_presorted_input() {
+        let mut archive_writer = PlacedArchiveWriter::with_options(
+            NamedTempFile::new().unwrap(),
+            PlacedArchiveWriterOptions {
+                presorted_input: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        for ms in [0, 2] {
+            archive_writer
+                .add_tile(
+                    0,
+                    0,
+                    [0, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(ms).unwrap(),
+                )
+                .unwrap();
+        }
+
+        assert!(matches!(
+            archive_writer.add_tile(
+                0,
+                0,
+                [0, 0, 0, 255],
+                NaiveDateTime::from_timestamp_millis(1).unwrap()
+            ),
+            Err(PlacedArchiveWriteError::UnsortedInput)
+        ));
+    }
+
+    #[test]
+    fn rejects_empty_archive() {
+        let mut archive_writer = PlacedArchiveWriter::new(NamedTempFile::new().unwrap()).unwrap();
+
+        assert!(matches!(
+            archive_writer.finalize(),
+            Err(PlacedArchiveWriteError::EmptyArchive)
+        ));
+    }
+
+    #[test]
+    fn rejects_color_table_overflow() {
+        let mut archive_writer = PlacedArchiveWriter::new(NamedTempFile::new().unwrap()).unwrap();
+        let placed_at = NaiveDateTime::from_timestamp_millis(0).unwrap();
+
+        for i in 0..StoredTilePlacement::PADDING_COLOR_INDEX {
+            archive_writer
+                .add_tile(0, 0, [i as u8, (i >> 8) as u8, 0, 255], placed_at)
+                .unwrap();
+        }
+
+        // Known colors can still be added
+        archive_writer
+            .add_tile(0, 0, [0, 0, 0, 255], placed_at)
+            .unwrap();
+        assert!(matches!(
+            archive_writer.add_tile(0, 0, [0, 0, 1, 255], placed_at),
+            Err(PlacedArchiveWriteError::ColorTableOverflow)
+        ));
+    }
+
+    #[test]
+    fn more_than_256_colors() {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer = PlacedArchiveWriter::new(writeable_file).unwrap();
+
+        let colors = (0..1000u16)
+            .map(|i| [i as u8, (i >> 8) as u8, 0, 255])
+            .collect::<Vec<_>>();
+        for (i, color) in colors.iter().enumerate() {
+            archive_writer
+                .add_tile(
+                    0,
+                    0,
+                    *color,
+                    NaiveDateTime::from_timestamp_millis(i as i64).unwrap(),
+                )
+                .unwrap();
+        }
+        archive_writer.finalize().unwrap();
+
+        let reader = PlacedArchiveReader::new(readable_file).unwrap();
+        assert_eq!(reader.meta.color_id_to_tuple.len(), 1000);
+        assert_eq!(reader.map(|tile| tile.color).collect::<Vec<_>>(), colors);
+    }
+
+    #[test]
+    fn infers_canvas_size_changes() {
+        // Tiles are placed in rows of 1000, so the canvas grows by one row every 1000 tiles
+        let reader = write_archive(
+            0..3000,
+            with_chunking_strategy(ChunkingStrategy::TileCount(500)),
+        );
+
+        assert_eq!(
+            reader.meta.canvas_size_changes,
+            vec![
+                CanvasSizeChange {
+                    width: 500,
+                    height: 1,
+                    ms_since_epoch: 0,
+                },
+                CanvasSizeChange {
+                    width: 1000,
+                    height: 1,
+                    ms_since_epoch: 500,
+                },
+                CanvasSizeChange {
+                    width: 1000,
+                    height: 2,
+                    ms_since_epoch: 1000,
+                },
+                CanvasSizeChange {
+                    width: 1000,
+                    height: 3,
+                    ms_since_epoch: 2000,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn explicit_canvas_size_changes() {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer = PlacedArchiveWriter::with_options(
+            writeable_file,
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(100),
+                generate_snapshots: true,
+                infer_canvas_size_changes: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        archive_writer
+            .add_canvas_size_change(20, 10, NaiveDateTime::from_timestamp_millis(500).unwrap())
+            .unwrap();
+        archive_writer
+            .add_canvas_size_change(10, 10, NaiveDateTime::from_timestamp_millis(0).unwrap())
+            .unwrap();
+
+        for i in 0..1000 {
+            // The second half of the tiles is placed in the area added by the expansion
+            let x = if i < 500 { i % 10 } else { 10 + i % 10 };
+            archive_writer
+                .add_tile(
+                    x as u16,
+                    (i % 7) as u16,
+                    [0, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(i).unwrap(),
                 )
                 .unwrap();
+        }
+
+        archive_writer.finalize().unwrap();
+
+        let mut reader = PlacedArchiveReader::new(readable_file).unwrap();
+        assert_eq!(
+            reader.meta.canvas_size_changes,
+            vec![
+                CanvasSizeChange {
+                    width: 10,
+                    height: 10,
+                    ms_since_epoch: 0,
+                },
+                CanvasSizeChange {
+                    width: 20,
+                    height: 10,
+                    ms_since_epoch: 500,
+                },
+            ]
+        );
+        assert_eq!(reader.canvas_at(499).unwrap().dimensions(), (10, 10));
+        // Starts from a snapshot taken before the expansion
+        let canvas = reader.canvas_at(550).unwrap();
+        assert_eq!(canvas.dimensions(), (20, 10));
+        assert_eq!(canvas.get_pixel(0, 0), &image::Rgba([0, 0, 0, 255]));
+        assert_eq!(canvas.get_pixel(19, 9), &image::Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn rejects_tile_out_of_bounds() {
+        let mut archive_writer = PlacedArchiveWriter::with_options(
+            NamedTempFile::new().unwrap(),
+            PlacedArchiveWriterOptions {
+                presorted_input: true,
+                infer_canvas_size_changes: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let placed_at = NaiveDateTime::from_timestamp_millis(0).unwrap();
+
+        archive_writer
+            .add_canvas_size_change(10, 10, placed_at)
+            .unwrap();
+        archive_writer
+            .add_tile(9, 9, [0, 0, 0, 255], placed_at)
+            .unwrap();
+        assert!(matches!(
+            archive_writer.add_tile(10, 0, [0, 0, 0, 255], placed_at),
+            Err(PlacedArchiveWriteError::TileOutOfBounds { x: 10, y: 0 })
+        ));
+    }
+
+    #[test]
+    fn rejects_canvas_too_large() {
+        let mut archive_writer = PlacedArchiveWriter::with_options(
+            NamedTempFile::new().unwrap(),
+            PlacedArchiveWriterOptions {
+                presorted_input: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let placed_at = NaiveDateTime::from_timestamp_millis(0).unwrap();
+
+        archive_writer
+            .add_tile(65534, 0, [0, 0, 0, 255], placed_at)
+            .unwrap();
+        assert!(matches!(
+            archive_writer.add_tile(65535, 0, [0, 0, 0, 255], placed_at),
+            Err(PlacedArchiveWriteError::CanvasTooLarge { x: 65535, y: 0 })
+        ));
+    }
+
+    #[test]
+    fn long_running_canvas() {
+        let start_ms = 1_648_817_050_315;
+        // Longer than fits in 32 bits of milliseconds
+        let duration_ms = 60 * 24 * 60 * 60 * 1000;
+        let reader = write_archive(
+            (0..100).map(|i| start_ms + i * duration_ms / 99),
+            with_chunking_strategy(ChunkingStrategy::TileCount(10)),
+        );
+
+        let meta = reader.meta.clone();
+        assert_eq!(
+            meta.first_tile_placed_at(),
+            NaiveDateTime::from_timestamp_millis(start_ms)
+        );
+        assert_eq!(meta.last_tile_placed_at_ms_since_epoch, duration_ms as u64);
+
+        let tiles = reader.collect::<Vec<_>>();
+        assert_eq!(tiles.last().unwrap().ms_since_epoch, duration_ms as u64);
+        assert_eq!(
+            tiles.last().unwrap().placed_at(&meta),
+            NaiveDateTime::from_timestamp_millis(start_ms + duration_ms)
+        );
+    }
+
+    #[test]
+    fn compact_tile_encoding() {
+        let write = |tile_encoding| {
+            let writeable_file = NamedTempFile::new().unwrap();
+            let readable_file = writeable_file.reopen().unwrap();
+            let mut archive_writer = PlacedArchiveWriter::with_options(
+                writeable_file,
+                PlacedArchiveWriterOptions {
+                    chunking_strategy: ChunkingStrategy::TileCount(1000),
+                    tile_encoding,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            for i in 0..10_000 {
+                archive_writer
+                    .add_tile(
+                        (i % 100) as u16,
+                        (i / 100) as u16,
+                        [(i % 3) as u8, 0, 0, 255],
+                        NaiveDateTime::from_timestamp_millis(i * 3).unwrap(),
+                    )
+                    .unwrap();
+            }
+            archive_writer.finalize().unwrap();
+
+            let archive_size = readable_file.metadata().unwrap().len();
+            (
+                archive_size,
+                PlacedArchiveReader::new(readable_file).unwrap(),
+            )
+        };
+
+        let (fixed_size, mut fixed_reader) = write(TileEncoding::Fixed);
+        let (compact_size, mut compact_reader) = write(TileEncoding::Compact);
+
+        assert_eq!(compact_reader.meta.tile_encoding, TileEncoding::Compact);
+        assert!(compact_size < fixed_size / 2);
+
+        assert_eq!(compact_reader.seek_to_ms(4500).unwrap(), 1500);
+        assert_eq!(compact_reader.next().unwrap().ms_since_epoch, 4500);
+
+        // Tiles on either side of a block boundary of the offset index
+        for tile_index in [1255, 1256, 1257] {
+            compact_reader.seek_to_tile_index(tile_index).unwrap();
+            assert_eq!(
+                compact_reader.next().unwrap().ms_since_epoch,
+                tile_index * 3
+            );
+        }
+
+        compact_reader.seek_to_tile_index(10_000).unwrap();
+        fixed_reader.seek_to_tile_index(10_000).unwrap();
+        assert!(compact_reader.rev_tiles().eq(fixed_reader.rev_tiles()));
+
+        let mut compact_bytes = Vec::new();
+        let mut fixed_bytes = Vec::new();
+        compact_reader.seek_to_tile_index(0).unwrap();
+        fixed_reader.seek_to_tile_index(0).unwrap();
+        std::io::Read::read_to_end(&mut compact_reader, &mut compact_bytes).unwrap();
+        std::io::Read::read_to_end(&mut fixed_reader, &mut fixed_bytes).unwrap();
+        assert_eq!(compact_bytes, fixed_bytes);
+
+        compact_reader.seek_to_tile_index(0).unwrap();
+        fixed_reader.seek_to_tile_index(0).unwrap();
+        assert!(compact_reader.eq(fixed_reader));
+    }
+
+    #[test]
+    fn frame_grid_tile_encoding() {
+        let frame_ms = NonZeroU32::new(2000).unwrap();
+        let write = |tile_encoding| {
+            let writeable_file = NamedTempFile::new().unwrap();
+            let readable_file = writeable_file.reopen().unwrap();
+            let mut archive_writer = PlacedArchiveWriter::with_options(
+                writeable_file,
+                PlacedArchiveWriterOptions {
+                    chunking_strategy: ChunkingStrategy::TileCount(2000),
+                    tile_encoding,
+                    generate_snapshots: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            // Every cell of a 100x10 canvas is placed twice within each frame
+            for i in 0..4000 {
+                archive_writer
+                    .add_tile(
+                        (i % 100) as u16,
+                        ((i / 100) % 10) as u16,
+                        [(i % 3) as u8, 0, 0, 255],
+                        NaiveDateTime::from_timestamp_millis(i).unwrap(),
+                    )
+                    .unwrap();
             }
+            archive_writer.finalize().unwrap();
+
+            PlacedArchiveReader::new(readable_file).unwrap()
+        };
+
+        let mut fixed_reader = write(TileEncoding::Fixed);
+        let mut frame_grid_reader = write(TileEncoding::FrameGrid { frame_ms });
+
+        assert_eq!(
+            frame_grid_reader.meta.tile_encoding,
+            TileEncoding::FrameGrid { frame_ms }
+        );
+        assert_eq!(
+            frame_grid_reader
+                .meta
+                .chunk_descs
+                .iter()
+                .map(|chunk| (chunk.num_tiles, chunk.up_to_ms_since_epoch))
+                .collect::<Vec<_>>(),
+            vec![(1000, 0), (1000, 2000)]
+        );
+
+        assert_eq!(frame_grid_reader.seek_to_ms(1500).unwrap(), 1000);
+        let tile = frame_grid_reader.next().unwrap();
+        assert_eq!((tile.x, tile.y, tile.ms_since_epoch), (0, 0, 2000));
+        // The later of the cell's two placements in the frame wins
+        assert_eq!(tile.color, [(3000 % 3) as u8, 0, 0, 255]);
+
+        for ms in [1999, 3999] {
+            assert_eq!(
+                frame_grid_reader.canvas_at(ms).unwrap(),
+                fixed_reader.canvas_at(ms).unwrap()
+            );
+        }
+
+        assert_eq!(frame_grid_reader.meta.total_tile_placements, 2000);
+        assert!(frame_grid_reader.verify().is_ok());
+    }
+
+    #[test]
+    fn frame_grid_chunks_end_between_frames() {
+        let frame_ms = NonZeroU32::new(1000).unwrap();
+        for chunking_strategy in [
+            ChunkingStrategy::TileCount(70),
+            ChunkingStrategy::MaxBytes(500),
+        ] {
+            let writeable_file = NamedTempFile::new().unwrap();
+            let readable_file = writeable_file.reopen().unwrap();
+            let mut archive_writer = PlacedArchiveWriter::with_options(
+                writeable_file,
+                PlacedArchiveWriterOptions {
+                    chunking_strategy,
+                    tile_encoding: TileEncoding::FrameGrid { frame_ms },
+                    ..Default::default()
+                },
+            )
+            .unwrap();
 
-            self.mla
-                .add_file(
-                    format!("tiles/{}", i).as_str(),
-                    tile_buf.len() as u64,
-                    tile_buf.as_slice(),
+            // 100 placements per frame, on 20 cells, so limits are reached in the middle of frames
+            for i in 0..2000 {
+                archive_writer
+                    .add_tile(
+                        (i % 20) as u16,
+                        0,
+                        [(i % 3) as u8, 0, 0, 255],
+                        NaiveDateTime::from_timestamp_millis(i * 10).unwrap(),
+                    )
+                    .unwrap();
+            }
+            archive_writer.finalize().unwrap();
+
+            let mut reader = PlacedArchiveReader::new(readable_file).unwrap();
+            assert!(reader.meta.chunk_descs.len() > 1);
+            let mut previous_chunk_end = None;
+            for chunk_id in 0..reader.meta.chunk_descs.len() as u32 {
+                let tiles = reader.read_chunk(chunk_id).unwrap();
+                let first_frame = tiles.first().unwrap().ms_since_epoch;
+                assert!(previous_chunk_end < Some(first_frame));
+                previous_chunk_end = Some(tiles.last().unwrap().ms_since_epoch);
+            }
+            // Each frame keeps exactly one placement per cell
+            assert_eq!(reader.meta.total_tile_placements, 20 * 20);
+            assert!(reader.verify().is_ok());
+        }
+    }
+
+    #[test]
+    fn frame_grid_canvas_size_changes() {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer = PlacedArchiveWriter::with_options(
+            writeable_file,
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(1),
+                tile_encoding: TileEncoding::FrameGrid {
+                    frame_ms: NonZeroU32::new(60_000).unwrap(),
+                },
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for (x, y, ms) in [(0, 0, 0), (50, 50, 40_000)] {
+            archive_writer
+                .add_tile(
+                    x,
+                    y,
+                    [0, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(ms).unwrap(),
                 )
                 .unwrap();
+        }
+        archive_writer.finalize().unwrap();
 
-            chunk_descs.push(ChunkDescription {
-                id: i as u32,
-                up_to_ms_since_epoch: tiles
-                    .last()
-                    .unwrap()
-                    .placed_at
-                    .signed_duration_since(first_tile_placed_at)
-                    .num_milliseconds() as u32,
-                num_tiles: tiles.len() as u32,
-            });
-        }
-
-        // todo
-        let canvas_size_changes = vec![CanvasSizeChange {
-            ms_since_epoch: 0,
-            width: 2000,
-            height: 2000,
-        }];
+        // Both tiles are stored at the start of the frame, so the canvas has to be large enough for them from then on
+        let mut reader = PlacedArchiveReader::new(readable_file).unwrap();
+        assert_eq!(
+            reader.meta.canvas_size_changes,
+            vec![CanvasSizeChange {
+                width: 51,
+                height: 51,
+                ms_since_epoch: 0,
+            }]
+        );
+        let canvas = reader.canvas_at(10_000).unwrap();
+        assert_eq!(canvas.get_pixel(50, 50).0, [0, 0, 0, 255]);
+        assert!(reader.verify().is_ok());
+    }
 
-        let meta = Meta {
-            canvas_size_changes,
-            chunk_descs,
-            last_tile_placed_at_ms_since_epoch: self
-                .tile_placements
-                .last()
+    #[test]
+    fn records_chunk_footprints() {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer = PlacedArchiveWriter::with_options(
+            writeable_file,
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(4),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // A diagonal line in the first chunk, a horizontal one in the second and two distant pixels in the third
+        let tiles = [
+            (10, 10),
+            (11, 11),
+            (12, 12),
+            (13, 13),
+            (50, 5),
+            (51, 5),
+            (52, 5),
+            (53, 5),
+            (200, 200),
+            (1200, 1200),
+        ];
+        for (i, (x, y)) in tiles.into_iter().enumerate() {
+            archive_writer
+                .add_tile(
+                    x,
+                    y,
+                    [0, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(i as i64).unwrap(),
+                )
+                .unwrap();
+        }
+        archive_writer.finalize().unwrap();
+
+        let mut reader = PlacedArchiveReader::new(readable_file).unwrap();
+        let footprints = (0..3)
+            .map(|chunk_id| reader.chunk_footprint(chunk_id).unwrap().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            footprints[0].bounding_box,
+            BoundingBox {
+                min_x: 10,
+                min_y: 10,
+                max_x: 13,
+                max_y: 13
+            }
+        );
+        assert!(footprints[0].touches(11, 11));
+        assert!(!footprints[0].touches(12, 11));
+        assert!(!footprints[0].touches(0, 0));
+
+        assert!(footprints[1].touches(53, 5));
+        assert!(!footprints[1].touches(54, 5));
+
+        assert!(footprints[2].touches(1200, 1200));
+        assert!(!footprints[2].touches(700, 700));
+        // Two pixels in a large bounding box are stored as runs instead of a bitmap of the whole box
+        assert!(
+            bincode::encode_to_vec(&footprints[2], BINCODE_CONFIG)
                 .unwrap()
-                .placed_at
-                .signed_duration_since(first_tile_placed_at)
-                .num_milliseconds() as u32,
-            total_tile_placements: self.tile_placements.len() as u64,
-            color_id_to_tuple: BTreeMap::from_iter(
-                self.color_tuple_to_id.iter().map(|(k, v)| (*v, *k)),
-            ),
+                .len()
+                < 64
+        );
+
+        let region = |min_x, min_y, max_x, max_y| BoundingBox {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
         };
+        let mut chunks_touching = |region| reader.chunks_touching(region).unwrap();
+        assert_eq!(chunks_touching(region(0, 0, 100, 100)), vec![0, 1]);
+        assert_eq!(chunks_touching(region(12, 0, 51, 11)), vec![1]);
+        assert_eq!(chunks_touching(region(13, 12, 20, 20)), vec![0]);
+        assert_eq!(chunks_touching(region(13, 10, 20, 12)), Vec::<u32>::new());
+        assert_eq!(
+            chunks_touching(region(600, 600, 800, 800)),
+            Vec::<u32>::new()
+        );
+        assert_eq!(chunks_touching(region(1100, 1100, 1300, 1300)), vec![2]);
+    }
 
-        let mut meta_buf = Vec::new();
-        bincode::encode_into_std_write(meta.clone(), &mut meta_buf, BINCODE_CONFIG).unwrap();
-        self.mla
-            .add_file("meta", meta_buf.len() as u64, meta_buf.as_slice())
+    #[test]
+    fn records_tile_placers() {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer = PlacedArchiveWriter::with_options(
+            writeable_file,
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(10),
+                max_tiles_in_memory: 7,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // Added in reverse, so placers have to follow their tiles through sorting
+        for i in (0..50).rev() {
+            let placed_at = NaiveDateTime::from_timestamp_millis(i).unwrap();
+            match i {
+                0..=9 => archive_writer.add_tile(0, 0, [0, 0, 0, 255], placed_at),
+                _ => archive_writer.add_tile_placed_by(
+                    0,
+                    0,
+                    [0, 0, 0, 255],
+                    placed_at,
+                    &format!("user{}", i % 3),
+                ),
+            }
             .unwrap();
+        }
+        archive_writer.finalize().unwrap();
 
-        // Generate snapshots
-        if should_generate_snapshots {
-            let largest_canvas_size = meta.get_largest_canvas_size().unwrap();
-            let mut canvas = RgbImage::new(
-                largest_canvas_size.width as u32,
-                largest_canvas_size.height as u32,
-            );
-            canvas.fill(0xff);
+        let mut reader = PlacedArchiveReader::new(readable_file).unwrap();
+        assert_eq!(reader.meta.num_users, Some(3));
 
-            let mut num_of_processed_tiles = 0;
-            for chunk in meta.chunk_descs {
-                for tile in self.tile_placements[num_of_processed_tiles..]
-                    .iter()
-                    .take(chunk.num_tiles as usize)
-                {
-                    canvas.put_pixel(
-                        tile.x as u32,
-                        tile.y as u32,
-                        image::Rgb(
-                            meta.color_id_to_tuple[&tile.color_index][0..3]
-                                .try_into()
-                                .unwrap(),
-                        ),
-                    );
-                }
+        let user_table = reader.user_table().unwrap().unwrap();
+        let placers = reader
+            .by_ref()
+            .map(|tile| {
+                tile.user_id
+                    .map(|user_id| user_table[user_id as usize].clone())
+            })
+            .collect::<Vec<_>>();
+        let expected_placers = (0..50)
+            .map(|i| (i >= 10).then(|| format!("user{}", i % 3)))
+            .collect::<Vec<_>>();
+        assert_eq!(placers, expected_placers);
+
+        reader.seek_to_tile_index(25).unwrap();
+        assert_eq!(
+            reader.next().unwrap().user_id,
+            Some(user_table.iter().position(|user| user == "user1").unwrap() as u32)
+        );
+    }
+
+    #[test]
+    fn archives_without_placers() {
+        let mut reader = write_archive(0..10, PlacedArchiveWriterOptions::default());
 
-                num_of_processed_tiles += chunk.num_tiles as usize;
+        assert_eq!(reader.meta.num_users, None);
+        assert_eq!(reader.user_table().unwrap(), None);
+        assert!(reader.all(|tile| tile.user_id.is_none()));
+    }
+
+    #[test]
+    fn moderator_edits() {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer = PlacedArchiveWriter::new(writeable_file).unwrap();
+        archive_writer
+            .add_canvas_size_change(10, 10, NaiveDateTime::from_timestamp_millis(0).unwrap())
+            .unwrap();
+
+        let rectangle = EditShape::Rectangle(BoundingBox {
+            min_x: 1,
+            min_y: 2,
+            max_x: 3,
+            max_y: 3,
+        });
+        let circle = EditShape::Circle {
+            x: 1,
+            y: 1,
+            radius: 2,
+        };
+        archive_writer
+            .add_tile(
+                9,
+                9,
+                [0, 0, 0, 255],
+                NaiveDateTime::from_timestamp_millis(0).unwrap(),
+            )
+            .unwrap();
+        archive_writer
+            .add_moderator_edit(
+                circle,
+                [0, 0, 255, 255],
+                NaiveDateTime::from_timestamp_millis(20).unwrap(),
+            )
+            .unwrap();
+        archive_writer
+            .add_moderator_edit(
+                rectangle,
+                [255, 0, 0, 255],
+                NaiveDateTime::from_timestamp_millis(10).unwrap(),
+            )
+            .unwrap();
+        archive_writer.finalize().unwrap();
+
+        let mut reader = PlacedArchiveReader::new(readable_file).unwrap();
+        assert_eq!(
+            reader.meta.moderator_edits,
+            vec![
+                ModeratorEdit {
+                    ms_since_epoch: 10,
+                    shape: rectangle,
+                    color_index: 2,
+                },
+                ModeratorEdit {
+                    ms_since_epoch: 20,
+                    shape: circle,
+                    color_index: 1,
+                },
+            ]
+        );
+        // 6 pixels in the rectangle, and 11 in the circle (clipped at the top and left edge)
+        assert_eq!(reader.meta.total_tile_placements, 1 + 6 + 11);
+
+        let canvas = reader.canvas_at(15).unwrap();
+        assert_eq!(canvas.get_pixel(3, 3).0, [255, 0, 0, 255]);
+        assert_eq!(canvas.get_pixel(3, 1).0, [255, 255, 255, 255]);
+
+        let canvas = reader.canvas_at(20).unwrap();
+        assert_eq!(canvas.get_pixel(3, 1).0, [0, 0, 255, 255]);
+        assert_eq!(canvas.get_pixel(3, 3).0, [255, 0, 0, 255]);
+        assert_eq!(canvas.get_pixel(1, 3).0, [0, 0, 255, 255]);
+    }
 
-                let mut temp_snapshot = tempfile().unwrap();
-                let mut buf = Vec::new();
-                // needs a seekable writer
-                canvas
-                    .write_to(&mut temp_snapshot, image::ImageOutputFormat::Png)
+    #[test]
+    fn append_to_archive() {
+        let options = PlacedArchiveWriterOptions {
+            chunking_strategy: ChunkingStrategy::TileCount(100),
+            generate_snapshots: true,
+            ..Default::default()
+        };
+        // Inferred canvas size changes depend on chunk boundaries, which differ after appending
+        let add_tiles = |archive_writer: &mut PlacedArchiveWriter<File>, tiles: Range<i64>| {
+            if tiles.start == 0 {
+                archive_writer
+                    .add_canvas_size_change(
+                        32,
+                        32,
+                        NaiveDateTime::from_timestamp_millis(0).unwrap(),
+                    )
                     .unwrap();
-                temp_snapshot.seek(SeekFrom::Start(0)).unwrap();
-                temp_snapshot.read_to_end(&mut buf).unwrap();
-
-                self.mla
-                    .add_file(
-                        format!("snapshots/{}", chunk.id).as_str(),
-                        buf.len() as u64,
-                        buf.as_slice(),
+            }
+            for i in tiles {
+                archive_writer
+                    .add_tile_placed_by(
+                        (i % 32) as u16,
+                        (i / 32 % 32) as u16,
+                        [(i % 300 / 100) as u8, (i / 300) as u8, 0, 255],
+                        NaiveDateTime::from_timestamp_millis(i).unwrap(),
+                        &format!("user{}", i % 7),
                     )
                     .unwrap();
             }
+        };
+
+        let first_part_file = NamedTempFile::new().unwrap();
+        let mut archive_writer =
+            PlacedArchiveWriter::with_options(first_part_file.reopen().unwrap(), options.clone())
+                .unwrap();
+        add_tiles(&mut archive_writer, 0..450);
+        archive_writer.finalize().unwrap();
+
+        let appended_file = NamedTempFile::new().unwrap();
+        let mut archive_writer = PlacedArchiveWriter::append(
+            first_part_file.reopen().unwrap(),
+            appended_file.reopen().unwrap(),
+            options.clone(),
+        )
+        .unwrap();
+        assert!(matches!(
+            archive_writer.add_tile(
+                0,
+                0,
+                [0, 0, 0, 255],
+                NaiveDateTime::from_timestamp_millis(448).unwrap()
+            ),
+            Err(PlacedArchiveWriteError::UnsortedInput)
+        ));
+        add_tiles(&mut archive_writer, 450..1000);
+        archive_writer.finalize().unwrap();
+
+        let whole_file = NamedTempFile::new().unwrap();
+        let mut archive_writer =
+            PlacedArchiveWriter::with_options(whole_file.reopen().unwrap(), options).unwrap();
+        add_tiles(&mut archive_writer, 0..1000);
+        archive_writer.finalize().unwrap();
+
+        let mut appended_reader =
+            PlacedArchiveReader::new(appended_file.reopen().unwrap()).unwrap();
+        let mut whole_reader = PlacedArchiveReader::new(whole_file.reopen().unwrap()).unwrap();
+
+        // The partial chunk at the end of the first part stays as it is
+        assert_eq!(
+            appended_reader
+                .meta
+                .chunk_descs
+                .iter()
+                .map(|chunk_desc| chunk_desc.num_tiles)
+                .collect::<Vec<_>>(),
+            vec![100, 100, 100, 100, 50, 100, 100, 100, 100, 100, 50]
+        );
+        assert_eq!(appended_reader.meta.total_tile_placements, 1000);
+        assert_eq!(
+            appended_reader.meta.color_id_to_tuple,
+            whole_reader.meta.color_id_to_tuple
+        );
+        assert_eq!(
+            appended_reader.user_table().unwrap(),
+            whole_reader.user_table().unwrap()
+        );
+
+        for ms in [300, 449, 450, 999] {
+            assert_eq!(
+                appended_reader.canvas_at(ms).unwrap(),
+                whole_reader.canvas_at(ms).unwrap()
+            );
+        }
+
+        let region = BoundingBox {
+            min_x: 3,
+            min_y: 4,
+            max_x: 3,
+            max_y: 4,
+        };
+        assert_eq!(
+            appended_reader.pixel_history(region).unwrap(),
+            whole_reader.pixel_history(region).unwrap()
+        );
+
+        appended_reader.seek_to_tile_index(0).unwrap();
+        whole_reader.seek_to_tile_index(0).unwrap();
+        assert!(appended_reader.eq(whole_reader));
+    }
+
+    #[test]
+    fn append_to_encrypted_archive() {
+        let private_key = StaticSecret::from([7; 32]);
+        let options = PlacedArchiveWriterOptions {
+            chunking_strategy: ChunkingStrategy::TileCount(4),
+            encryption_public_keys: vec![PublicKey::from(&private_key)],
+            ..Default::default()
+        };
+        let add_tiles = |archive_writer: &mut PlacedArchiveWriter<File>, tiles: Range<i64>| {
+            for ms in tiles {
+                archive_writer
+                    .add_tile(
+                        ms as u16,
+                        0,
+                        [0, 0, 0, 255],
+                        NaiveDateTime::from_timestamp_millis(ms).unwrap(),
+                    )
+                    .unwrap();
+            }
+        };
+
+        let first_part_file = NamedTempFile::new().unwrap();
+        let mut archive_writer =
+            PlacedArchiveWriter::with_options(first_part_file.reopen().unwrap(), options.clone())
+                .unwrap();
+        add_tiles(&mut archive_writer, 0..10);
+        archive_writer.finalize().unwrap();
+
+        let appended_file = NamedTempFile::new().unwrap();
+        assert!(matches!(
+            PlacedArchiveWriter::append(
+                first_part_file.reopen().unwrap(),
+                appended_file.reopen().unwrap(),
+                options.clone(),
+            ),
+            Err(PlacedArchiveWriteError::CouldNotReadArchive(_))
+        ));
+
+        let mut archive_writer = PlacedArchiveWriter::append_with_private_keys(
+            first_part_file.reopen().unwrap(),
+            std::slice::from_ref(&private_key),
+            appended_file.reopen().unwrap(),
+            options,
+        )
+        .unwrap();
+        add_tiles(&mut archive_writer, 10..20);
+        archive_writer.finalize().unwrap();
+
+        assert!(PlacedArchiveReader::new(appended_file.reopen().unwrap()).is_err());
+        let mut reader =
+            PlacedArchiveReader::with_private_keys(appended_file.reopen().unwrap(), &[private_key])
+                .unwrap();
+        assert_eq!(reader.meta.total_tile_placements, 20);
+        assert!(reader.verify().is_ok());
+        reader.seek_to_tile_index(0).unwrap();
+        assert_eq!(
+            reader.map(|tile| tile.x).collect::<Vec<_>>(),
+            (0..20).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn encrypted_archive() {
+        let private_key = StaticSecret::from([7; 32]);
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer = PlacedArchiveWriter::with_options(
+            writeable_file,
+            PlacedArchiveWriterOptions {
+                encryption_public_keys: vec![PublicKey::from(&private_key)],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for ms in 0..10 {
+            archive_writer
+                .add_tile(
+                    ms as u16,
+                    0,
+                    [0, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(ms).unwrap(),
+                )
+                .unwrap();
         }
+        archive_writer.finalize().unwrap();
+
+        assert!(PlacedArchiveReader::new(readable_file.try_clone().unwrap()).is_err());
+        let other_private_key = StaticSecret::from([8; 32]);
+        assert!(PlacedArchiveReader::with_private_keys(
+            readable_file.try_clone().unwrap(),
+            std::slice::from_ref(&other_private_key)
+        )
+        .is_err());
 
-        self.mla.finalize().unwrap();
+        let reader = PlacedArchiveReader::with_private_keys(
+            readable_file,
+            &[other_private_key, private_key],
+        )
+        .unwrap();
+        assert_eq!(reader.meta.total_tile_placements, 10);
+        assert_eq!(
+            reader.map(|tile| tile.x).collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
     }
 }
@@ -0,0 +1,634 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    future::Future,
+    io::{Read, Seek, SeekFrom},
+    ops::Range,
+    pin::Pin,
+    sync::{mpsc, Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt},
+    sync::oneshot,
+};
+use x25519_dalek::StaticSecret;
+
+use crate::{
+    archive_reader::{decode_chunk_files, ChunkFiles},
+    errors::{PlacedArchiveError, ReadError},
+    mla_layout::{self, FileLocation},
+    structures::{DecodedTilePlacement, Meta},
+    PlacedArchiveReader,
+};
+
+/// Size of the blocks fetched from the source.
+const FETCH_BLOCK_SIZE: u64 = 64 * 1024;
+/// Number of blocks kept in memory for reading archives with layers.
+const MAX_CACHED_BLOCKS: usize = 256;
+/// Upper bound for the size of a user id in a chunk's user table.
+const MAX_ENCODED_USER_ID_SIZE: u64 = 5;
+
+/// Reader for archives in an `AsyncRead + AsyncSeek` source, e.g. a file or object served by an async service.
+///
+/// The archive's metadata is decoded once when opening it. For archives without layers, the files of a chunk are then
+/// fetched in one read each, at the location recorded in the archive's index. MLA can only decode compressed or
+/// encrypted archives synchronously, so for those an MLA reader lives on a thread of its own and reads the chunk's
+/// files from the blocks of the source fetched for it.
+pub struct AsyncPlacedArchiveReader<S> {
+    blocks: BlockCache<S>,
+    chunk_files: ChunkFileSource,
+    /// Format version the archive was written with, see `FORMAT_VERSION`
+    pub format_version: u32,
+    pub meta: Meta,
+    next_chunk_id: u32,
+    current_chunk_tiles: VecDeque<DecodedTilePlacement>,
+}
+
+/// Where the files of chunks are read from.
+enum ChunkFileSource {
+    /// Location of each file in an archive without layers
+    Layout(HashMap<String, FileLocation>),
+    /// Thread owning the MLA reader of an archive with layers
+    MlaThread(mpsc::Sender<MlaJob>),
+}
+
+type MlaReader = PlacedArchiveReader<'static, FetchedBlocks>;
+type MlaJob = Box<dyn FnOnce(&mut Option<MlaReader>) + Send>;
+
+impl<S: AsyncRead + AsyncSeek + Unpin> AsyncPlacedArchiveReader<S> {
+    pub async fn new(source: S) -> Result<Self, PlacedArchiveError> {
+        Self::with_private_keys(source, &[]).await
+    }
+
+    /// Opens an archive that may be encrypted for any of the recipients owning `private_keys`.
+    pub async fn with_private_keys(
+        mut source: S,
+        private_keys: &[StaticSecret],
+    ) -> Result<Self, PlacedArchiveError> {
+        let source_len = source
+            .seek(SeekFrom::End(0))
+            .await
+            .map_err(|err| PlacedArchiveError::MLAReadError(err.into()))?;
+
+        let mut blocks = BlockCache {
+            source,
+            source_len,
+            fetched: Arc::new(Mutex::new(FetchedBlockMap::default())),
+            fetch_order: VecDeque::new(),
+        };
+
+        // MLA readers can't be moved between threads, so the one reading the archive stays on the thread opening it
+        let (mla_jobs, mla_job_receiver) = mpsc::channel::<MlaJob>();
+        std::thread::spawn(move || {
+            let mut reader = None;
+            for job in mla_job_receiver {
+                job(&mut reader);
+            }
+        });
+
+        let private_keys = private_keys.to_vec();
+        let (format_version, meta, file_locations) = blocks
+            .run_on_mla_thread(
+                &mla_jobs,
+                move |reader, mut fetched_blocks| {
+                    let opened = PlacedArchiveReader::with_private_keys(
+                        fetched_blocks.clone(),
+                        &private_keys,
+                    )?;
+                    // Files in archives without layers are located through the index instead of being read by MLA
+                    let file_locations = mla_layout::read_header(&mut fetched_blocks)
+                        .and_then(|header_len| {
+                            mla_layout::read_index(&mut fetched_blocks, header_len)
+                        })
+                        .ok();
+
+                    let opened_meta = (opened.format_version, opened.meta.clone(), file_locations);
+                    *reader = Some(opened);
+                    Ok(opened_meta)
+                },
+                1,
+                |err| PlacedArchiveError::MLAReadError(err.into()),
+                || PlacedArchiveError::ReaderThreadExited,
+            )
+            .await?;
+
+        // Dropping the sender ends the MLA thread when it isn't needed
+        let chunk_files = match file_locations {
+            Some(file_locations) => ChunkFileSource::Layout(file_locations),
+            None => ChunkFileSource::MlaThread(mla_jobs),
+        };
+        blocks.evict();
+
+        Ok(AsyncPlacedArchiveReader {
+            blocks,
+            chunk_files,
+            format_version,
+            meta,
+            next_chunk_id: 0,
+            current_chunk_tiles: VecDeque::new(),
+        })
+    }
+
+    /// Reads every tile of a chunk, without moving the reader.
+    pub async fn read_chunk(
+        &mut self,
+        chunk_id: u32,
+    ) -> Result<Vec<DecodedTilePlacement>, ReadError> {
+        let fetch_err =
+            move |err: std::io::Error| ReadError::CouldNotFetchChunk(chunk_id, err.into());
+
+        let files = match &self.chunk_files {
+            ChunkFileSource::Layout(file_locations) => {
+                let tiles = match file_locations.get(&format!("tiles/{}", chunk_id)) {
+                    Some(location) => self.blocks.fetch_file(location).await.map_err(fetch_err)?,
+                    None => return Err(ReadError::MissingChunk(chunk_id)),
+                };
+                let users = match (
+                    self.meta.num_users,
+                    file_locations.get(&format!("users/{}", chunk_id)),
+                ) {
+                    (Some(_), Some(location)) => {
+                        Some(self.blocks.fetch_file(location).await.map_err(fetch_err)?)
+                    }
+                    _ => None,
+                };
+
+                ChunkFiles { tiles, users }
+            }
+            ChunkFileSource::MlaThread(mla_jobs) => {
+                // The position of the chunk's files is only known once MLA reaches them, from where enough blocks for
+                // the whole chunk are fetched
+                let num_blocks_per_fetch = match self.meta.chunk_descs.get(chunk_id as usize) {
+                    Some(chunk_desc) => {
+                        let max_tile_size = self.meta.tile_encoding.max_encoded_size() as u64
+                            + MAX_ENCODED_USER_ID_SIZE;
+                        (chunk_desc.num_tiles as u64 * max_tile_size).div_ceil(FETCH_BLOCK_SIZE)
+                    }
+                    None => 1,
+                };
+
+                let files = self
+                    .blocks
+                    .run_on_mla_thread(
+                        mla_jobs,
+                        move |reader, _| match reader {
+                            Some(reader) => reader.read_chunk_files(chunk_id),
+                            None => Err(ReadError::ReaderThreadExited(chunk_id)),
+                        },
+                        num_blocks_per_fetch.max(1),
+                        fetch_err,
+                        || ReadError::ReaderThreadExited(chunk_id),
+                    )
+                    .await;
+                self.blocks.evict();
+
+                files?
+            }
+        };
+
+        decode_chunk_files(&self.meta, self.format_version, chunk_id, files)
+    }
+
+    /// Returns the next tile, or an error if the archive is broken. Ends after the first error.
+    pub async fn next_tile(&mut self) -> Option<Result<DecodedTilePlacement, ReadError>> {
+        loop {
+            if let Some(tile) = self.current_chunk_tiles.pop_front() {
+                return Some(Ok(tile));
+            }
+
+            if self.next_chunk_id as usize >= self.meta.chunk_descs.len() {
+                return None;
+            }
+
+            match self.read_chunk(self.next_chunk_id).await {
+                Ok(tiles) => {
+                    self.current_chunk_tiles = tiles.into();
+                    self.next_chunk_id += 1;
+                }
+                Err(err) => {
+                    self.next_chunk_id = self.meta.chunk_descs.len() as u32;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// Blocks of an async source, fetched as they're needed.
+struct BlockCache<S> {
+    source: S,
+    source_len: u64,
+    fetched: Arc<Mutex<FetchedBlockMap>>,
+    // Indices of the fetched blocks, least recently fetched first
+    fetch_order: VecDeque<u64>,
+}
+
+#[derive(Default)]
+struct FetchedBlockMap {
+    blocks: HashMap<u64, Arc<Vec<u8>>>,
+    // First block that was read before it had been fetched
+    missing_block: Option<u64>,
+}
+
+impl<S: AsyncRead + AsyncSeek + Unpin> BlockCache<S> {
+    /// Runs `op` on the MLA thread, with a synchronous view of the blocks fetched so far. Whenever `op` tries to read a
+    /// block that hasn't been fetched yet, `num_blocks_per_fetch` blocks are fetched from there and `op` is run again,
+    /// fetching twice as many blocks every time to bound the number of tries.
+    async fn run_on_mla_thread<T, E>(
+        &mut self,
+        mla_jobs: &mpsc::Sender<MlaJob>,
+        op: impl Fn(&mut Option<MlaReader>, FetchedBlocks) -> Result<T, E> + Clone + Send + 'static,
+        mut num_blocks_per_fetch: u64,
+        map_fetch_err: impl Fn(std::io::Error) -> E,
+        thread_exited: impl Fn() -> E,
+    ) -> Result<T, E>
+    where
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        loop {
+            self.fetched.lock().unwrap().missing_block = None;
+            let fetched_blocks = FetchedBlocks {
+                fetched: self.fetched.clone(),
+                len: self.source_len,
+                pos: 0,
+            };
+
+            let (reply, result) = oneshot::channel();
+            let op = op.clone();
+            let job: MlaJob = Box::new(move |reader| {
+                let _ = reply.send(op(reader, fetched_blocks));
+            });
+            if mla_jobs.send(job).is_err() {
+                return Err(thread_exited());
+            }
+            let result = match result.await {
+                Ok(result) => result,
+                Err(_) => return Err(thread_exited()),
+            };
+
+            let missing_block = self.fetched.lock().unwrap().missing_block;
+            match missing_block {
+                Some(index) => {
+                    self.fetch_blocks(index..index + num_blocks_per_fetch)
+                        .await
+                        .map_err(&map_fetch_err)?;
+                    num_blocks_per_fetch *= 2;
+                }
+                None => return result,
+            }
+        }
+    }
+
+    /// Fetches the missing blocks among `indices` in a single read.
+    async fn fetch_blocks(&mut self, indices: Range<u64>) -> std::io::Result<()> {
+        let num_blocks = self.source_len.div_ceil(FETCH_BLOCK_SIZE);
+        let missing: Vec<_> = {
+            let fetched = self.fetched.lock().unwrap();
+            (indices.start..indices.end.min(num_blocks))
+                .filter(|index| !fetched.blocks.contains_key(index))
+                .collect()
+        };
+        let (first, last) = match (missing.first(), missing.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return Ok(()),
+        };
+
+        let start = first * FETCH_BLOCK_SIZE;
+        let end = ((last + 1) * FETCH_BLOCK_SIZE).min(self.source_len);
+        let mut buf = vec![0; (end - start) as usize];
+        self.source.seek(SeekFrom::Start(start)).await?;
+        self.source.read_exact(&mut buf).await?;
+
+        let mut fetched = self.fetched.lock().unwrap();
+        for (index, block) in (first..=last).zip(buf.chunks(FETCH_BLOCK_SIZE as usize)) {
+            if let Entry::Vacant(entry) = fetched.blocks.entry(index) {
+                entry.insert(Arc::new(block.to_vec()));
+                self.fetch_order.push_back(index);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops the least recently fetched blocks beyond `MAX_CACHED_BLOCKS`.
+    fn evict(&mut self) {
+        let mut fetched = self.fetched.lock().unwrap();
+        while self.fetch_order.len() > MAX_CACHED_BLOCKS {
+            let index = self.fetch_order.pop_front().unwrap();
+            fetched.blocks.remove(&index);
+        }
+    }
+
+    /// Fetches the content of a file in an archive without layers in a single read.
+    async fn fetch_file(&mut self, location: &FileLocation) -> std::io::Result<Vec<u8>> {
+        let range = location.range();
+        if range.end > self.source_len {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "File extends past the end of the archive",
+            ));
+        }
+
+        let mut buf = vec![0; (range.end - range.start) as usize];
+        self.source.seek(SeekFrom::Start(range.start)).await?;
+        self.source.read_exact(&mut buf).await?;
+
+        let content_ranges =
+            mla_layout::file_content_ranges(&buf, range.start, location).map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "Malformed archive")
+            })?;
+        let mut content = Vec::with_capacity(location.size as usize);
+        for content_range in content_ranges {
+            content.extend_from_slice(&buf[content_range]);
+        }
+
+        Ok(content)
+    }
+}
+
+impl<S: AsyncRead + AsyncSeek + Unpin + Send + 'static> AsyncPlacedArchiveReader<S> {
+    /// Turns the reader into a stream of the tiles from its current position, ending after the first error.
+    pub fn into_stream(self) -> TileStream<S> {
+        TileStream {
+            reader: Some(self),
+            pending: None,
+        }
+    }
+}
+
+type NextTileFuture<S> = Pin<
+    Box<
+        dyn Future<
+                Output = (
+                    AsyncPlacedArchiveReader<S>,
+                    Option<Result<DecodedTilePlacement, ReadError>>,
+                ),
+            > + Send,
+    >,
+>;
+
+/// Stream of the tiles in an archive. Created by `AsyncPlacedArchiveReader::into_stream()`.
+pub struct TileStream<S> {
+    reader: Option<AsyncPlacedArchiveReader<S>>,
+    pending: Option<NextTileFuture<S>>,
+}
+
+impl<S: AsyncRead + AsyncSeek + Unpin + Send + 'static> Stream for TileStream<S> {
+    type Item = Result<DecodedTilePlacement, ReadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.pending.is_none() {
+            let mut reader = match self.reader.take() {
+                Some(reader) => reader,
+                None => return Poll::Ready(None),
+            };
+            self.pending = Some(Box::pin(async move {
+                let tile = reader.next_tile().await;
+                (reader, tile)
+            }));
+        }
+
+        match self.pending.as_mut().unwrap().as_mut().poll(cx) {
+            Poll::Ready((reader, tile)) => {
+                self.pending = None;
+                self.reader = Some(reader);
+                Poll::Ready(tile)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Synchronous view of the blocks fetched from an async source. Reading from a block that hasn't been fetched fails
+/// and records the block, so it can be fetched before trying again.
+#[derive(Clone)]
+struct FetchedBlocks {
+    fetched: Arc<Mutex<FetchedBlockMap>>,
+    len: u64,
+    pos: u64,
+}
+
+impl Read for FetchedBlocks {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let index = self.pos / FETCH_BLOCK_SIZE;
+        let mut fetched = self.fetched.lock().unwrap();
+        let block = match fetched.blocks.get(&index) {
+            Some(block) => block,
+            None => {
+                fetched.missing_block.get_or_insert(index);
+                return Err(std::io::Error::other("Block hasn't been fetched yet"));
+            }
+        };
+
+        let offset_in_block = (self.pos - index * FETCH_BLOCK_SIZE) as usize;
+        let read_len = buf.len().min(block.len() - offset_in_block);
+        buf[..read_len].copy_from_slice(&block[offset_in_block..offset_in_block + read_len]);
+        self.pos += read_len as u64;
+
+        Ok(read_len)
+    }
+}
+
+impl Seek for FetchedBlocks {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        match new_pos {
+            Some(new_pos) => {
+                self.pos = new_pos;
+                Ok(new_pos)
+            }
+            None => Err(std::io::Error::other(
+                "Seeked before the start of the archive",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::poll_fn,
+        io::SeekFrom,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::{Context, Poll},
+    };
+
+    use chrono::NaiveDateTime;
+    use futures_core::Stream;
+    use tempfile::NamedTempFile;
+    use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    use crate::{
+        structures::{ChunkingStrategy, Compression},
+        PlacedArchiveReader, PlacedArchiveWriter, PlacedArchiveWriterOptions,
+    };
+
+    use super::{AsyncPlacedArchiveReader, ChunkFileSource, FETCH_BLOCK_SIZE};
+
+    fn write_archive(compression: Compression) -> NamedTempFile {
+        write_archive_with_options(PlacedArchiveWriterOptions {
+            compression,
+            ..Default::default()
+        })
+    }
+
+    fn write_archive_with_options(options: PlacedArchiveWriterOptions) -> NamedTempFile {
+        let file = NamedTempFile::new().unwrap();
+        let mut archive_writer = PlacedArchiveWriter::with_options(
+            file.reopen().unwrap(),
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(5000),
+                ..options
+            },
+        )
+        .unwrap();
+        // Large enough for uncompressed chunks to span several fetched blocks
+        for i in 0..30_000u32 {
+            archive_writer
+                .add_tile(
+                    (i % 1000) as u16,
+                    (i / 1000) as u16,
+                    [(i % 7) as u8, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(i as i64).unwrap(),
+                )
+                .unwrap();
+        }
+        archive_writer.finalize().unwrap();
+
+        file
+    }
+
+    #[tokio::test]
+    async fn streams_tiles() {
+        for compression in [Compression::None, Compression::Brotli(5)] {
+            let file = write_archive(compression);
+            let source = tokio::fs::File::open(file.path()).await.unwrap();
+            let reader = AsyncPlacedArchiveReader::new(source).await.unwrap();
+            let sync_reader = PlacedArchiveReader::new(file.reopen().unwrap()).unwrap();
+            assert_eq!(reader.meta, sync_reader.meta);
+            assert_eq!(reader.format_version, sync_reader.format_version);
+
+            let mut stream = reader.into_stream();
+            let mut tiles = Vec::new();
+            while let Some(tile) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+                tiles.push(tile.unwrap());
+            }
+            assert!(tiles.into_iter().eq(sync_reader));
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_single_chunk() {
+        let file = write_archive(Compression::None);
+        assert!(file.as_file().metadata().unwrap().len() > 4 * FETCH_BLOCK_SIZE);
+        let source = tokio::fs::File::open(file.path()).await.unwrap();
+        let mut reader = AsyncPlacedArchiveReader::new(source).await.unwrap();
+        let mut sync_reader = PlacedArchiveReader::new(file.reopen().unwrap()).unwrap();
+
+        assert_eq!(
+            reader.read_chunk(3).await.unwrap(),
+            sync_reader.read_chunk(3).unwrap()
+        );
+    }
+
+    /// Source counting how many times it's been seeked, i.e. how many separate reads were made.
+    struct CountingSource {
+        file: tokio::fs::File,
+        num_seeks: Arc<AtomicUsize>,
+    }
+
+    impl AsyncRead for CountingSource {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.file).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncSeek for CountingSource {
+        fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+            self.num_seeks.fetch_add(1, Ordering::Relaxed);
+            Pin::new(&mut self.file).start_seek(position)
+        }
+
+        fn poll_complete(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<u64>> {
+            Pin::new(&mut self.file).poll_complete(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn fetches_chunk_in_single_reads() {
+        let file = write_archive(Compression::None);
+        let num_seeks = Arc::new(AtomicUsize::new(0));
+        let source = CountingSource {
+            file: tokio::fs::File::open(file.path()).await.unwrap(),
+            num_seeks: num_seeks.clone(),
+        };
+        let mut reader = AsyncPlacedArchiveReader::new(source).await.unwrap();
+        let mut sync_reader = PlacedArchiveReader::new(file.reopen().unwrap()).unwrap();
+        assert!(matches!(reader.chunk_files, ChunkFileSource::Layout(_)));
+
+        for chunk_id in [3, 1, 3] {
+            let num_seeks_before = num_seeks.load(Ordering::Relaxed);
+            assert_eq!(
+                reader.read_chunk(chunk_id).await.unwrap(),
+                sync_reader.read_chunk(chunk_id).unwrap()
+            );
+            // One read each for the chunk's tiles and users, and none for a chunk that's still cached
+            let num_reads = num_seeks.load(Ordering::Relaxed) - num_seeks_before;
+            assert!(num_reads <= 2, "{} reads for chunk {}", num_reads, chunk_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_encrypted_archive() {
+        let private_key = StaticSecret::from([7; 32]);
+        let file = write_archive_with_options(PlacedArchiveWriterOptions {
+            encryption_public_keys: vec![PublicKey::from(&private_key)],
+            ..Default::default()
+        });
+
+        let source = tokio::fs::File::open(file.path()).await.unwrap();
+        assert!(AsyncPlacedArchiveReader::new(source).await.is_err());
+
+        let source = tokio::fs::File::open(file.path()).await.unwrap();
+        let mut reader =
+            AsyncPlacedArchiveReader::with_private_keys(source, std::slice::from_ref(&private_key))
+                .await
+                .unwrap();
+        let mut sync_reader =
+            PlacedArchiveReader::with_private_keys(file.reopen().unwrap(), &[private_key]).unwrap();
+        assert_eq!(reader.meta, sync_reader.meta);
+        assert!(matches!(reader.chunk_files, ChunkFileSource::MlaThread(_)));
+
+        // The same MLA reader reads every chunk, in any order
+        for chunk_id in [4, 0, 4, 2] {
+            assert_eq!(
+                reader.read_chunk(chunk_id).await.unwrap(),
+                sync_reader.read_chunk(chunk_id).unwrap()
+            );
+        }
+    }
+}
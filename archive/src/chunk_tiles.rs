@@ -0,0 +1,136 @@
+use std::io::Read;
+
+use bincode::error::DecodeError;
+
+use crate::{
+    constants::{BINCODE_CONFIG, COMPACT_TILE_INDEX_INTERVAL},
+    structures::StoredTilePlacement,
+    tile_encoding::{decode_compact_tiles_from, CompactTileIndex},
+};
+
+/// Tiles of a single chunk, read like consecutive fixed-width `StoredTilePlacement`s whatever the chunk's encoding.
+/// `TileEncoding::Compact` chunks stay encoded and are decoded `COMPACT_TILE_INDEX_INTERVAL` tiles at a time through their offset index.
+pub(crate) struct ChunkTiles {
+    tiles: Tiles,
+    /// Position within the fixed-width layout, in bytes
+    position: u64,
+}
+
+enum Tiles {
+    Fixed(Vec<u8>),
+    Compact {
+        buf: Vec<u8>,
+        index: CompactTileIndex,
+        // Index and fixed-width tiles of the most recently decoded block of `COMPACT_TILE_INDEX_INTERVAL` tiles
+        decoded_block: Option<(usize, Vec<u8>)>,
+    },
+}
+
+impl ChunkTiles {
+    pub fn fixed(buf: Vec<u8>) -> Self {
+        Self {
+            tiles: Tiles::Fixed(buf),
+            position: 0,
+        }
+    }
+
+    pub fn compact(buf: Vec<u8>, index: CompactTileIndex) -> Self {
+        Self {
+            tiles: Tiles::Compact {
+                buf,
+                index,
+                decoded_block: None,
+            },
+            position: 0,
+        }
+    }
+
+    /// Length of the chunk in the fixed-width layout, in bytes.
+    pub fn len(&self) -> u64 {
+        match &self.tiles {
+            Tiles::Fixed(buf) => buf.len() as u64,
+            Tiles::Compact { index, .. } => {
+                index.num_tiles as u64 * StoredTilePlacement::encoded_size() as u64
+            }
+        }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: u64) {
+        self.position = position;
+    }
+
+    /// Decodes the tile at `tile_index`, without moving the position.
+    pub fn tile(&mut self, tile_index: usize) -> Result<StoredTilePlacement, DecodeError> {
+        let bytes =
+            self.fixed_bytes_at((tile_index * StoredTilePlacement::encoded_size()) as u64)?;
+
+        bincode::decode_from_slice(bytes, BINCODE_CONFIG).map(|(tile, _)| tile)
+    }
+
+    /// Returns the fixed-width bytes from `position` up to the end of the chunk, or of the decoded block containing `position`.
+    fn fixed_bytes_at(&mut self, position: u64) -> Result<&[u8], DecodeError> {
+        let (buf, index, decoded_block) = match &mut self.tiles {
+            Tiles::Fixed(buf) => return Ok(buf.get(position as usize..).unwrap_or_default()),
+            Tiles::Compact {
+                buf,
+                index,
+                decoded_block,
+            } => (buf, index, decoded_block),
+        };
+
+        let encoded_size = StoredTilePlacement::encoded_size();
+        let tile_index = position as usize / encoded_size;
+        if tile_index >= index.num_tiles as usize {
+            return Ok(&[]);
+        }
+
+        let block_index = tile_index / COMPACT_TILE_INDEX_INTERVAL;
+        if decoded_block.as_ref().map(|(block_index, _)| *block_index) != Some(block_index) {
+            let offset = index
+                .offsets
+                .get(block_index)
+                .ok_or_else(|| DecodeError::OtherString("missing tile offset".to_string()))?;
+            let num_tiles = (index.num_tiles as usize - block_index * COMPACT_TILE_INDEX_INTERVAL)
+                .min(COMPACT_TILE_INDEX_INTERVAL);
+
+            let mut block = Vec::with_capacity(num_tiles * encoded_size);
+            decode_compact_tiles_from(buf, *offset, num_tiles, &mut block)?;
+            if block.len() != num_tiles * encoded_size {
+                return Err(DecodeError::OtherString(
+                    "chunk has fewer tiles than its index".to_string(),
+                ));
+            }
+
+            *decoded_block = Some((block_index, block));
+        }
+
+        let (_, block) = decoded_block.as_ref().unwrap();
+        Ok(&block[position as usize - block_index * COMPACT_TILE_INDEX_INTERVAL * encoded_size..])
+    }
+}
+
+impl Read for ChunkTiles {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut num_read = 0;
+
+        while num_read < buf.len() {
+            let bytes = self
+                .fixed_bytes_at(self.position)
+                .map_err(std::io::Error::other)?;
+            if bytes.is_empty() {
+                break;
+            }
+
+            let len = bytes.len().min(buf.len() - num_read);
+            buf[num_read..num_read + len].copy_from_slice(&bytes[..len]);
+            num_read += len;
+            self.position += len as u64;
+        }
+
+        Ok(num_read)
+    }
+}
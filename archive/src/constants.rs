@@ -1,5 +1,22 @@
-use bincode::config::{Configuration, Fixint, LittleEndian, NoLimit, WriteFixedArrayLength};
+use bincode::config::{
+    Configuration, Fixint, LittleEndian, NoLimit, Varint, WriteFixedArrayLength,
+};
 
 // Use legacy encoding for fixed-width integers (field size needs to be constant so we can seek)
 pub const BINCODE_CONFIG: Configuration<LittleEndian, Fixint, WriteFixedArrayLength, NoLimit> =
     bincode::config::legacy();
+
+// Variable-length integers for compact tile chunks and their offset indices, decoded a block of tiles at a time when read
+pub const COMPACT_BINCODE_CONFIG: Configuration<
+    LittleEndian,
+    Varint,
+    WriteFixedArrayLength,
+    NoLimit,
+> = bincode::config::standard().write_fixed_array_length();
+
+/// Number of tiles between entries of a compact chunk's offset index, which is also how many tiles are decoded at once when reading it
+pub const COMPACT_TILE_INDEX_INTERVAL: usize = 256;
+
+/// Version of the archive layout written by `PlacedArchiveWriter`. Bump this whenever `Meta`, `StoredTilePlacement` or the encoding of chunks change.
+/// Archives written before versioning was introduced don't contain a version file and are treated as version 0.
+pub const FORMAT_VERSION: u32 = 1;
@@ -3,6 +3,65 @@ pub enum PlacedArchiveError {
     MLAReadError(mla::errors::Error),
     MissingMetaFile,
     CouldNotDecodeMetaFile,
+    CouldNotDecodeVersionFile,
+    /// The archive was written by a newer version of this crate
+    UnsupportedFormatVersion(u32),
+    MissingUserTableFile,
+    CouldNotDecodeUserTableFile,
+    CouldNotDecodeFootprintFile,
+    /// The thread reading the archive exited before opening it, e.g. because it panicked
+    ReaderThreadExited,
+}
+
+#[derive(Debug)]
+pub enum PlacedArchiveWriteError {
+    MLAWriteError(mla::errors::Error),
+    IOError(std::io::Error),
+    CouldNotEncode(bincode::error::EncodeError),
+    CouldNotWriteSnapshot(image::ImageError),
+    /// `finalize()` was called before any tiles were added
+    EmptyArchive,
+    /// More distinct colors were added than color indices are available (see `StoredTilePlacement::PADDING_COLOR_INDEX`)
+    ColorTableOverflow,
+    /// A tile was added out of chronological order while `presorted_input` is set, or before the last tile of an appended archive
+    UnsortedInput,
+    /// A tile was placed outside of the canvas while `infer_canvas_size_changes` is disabled
+    TileOutOfBounds {
+        x: u16,
+        y: u16,
+    },
+    /// A tile was placed at the largest coordinate, so the inferred canvas would be too large to record
+    CanvasTooLarge {
+        x: u16,
+        y: u16,
+    },
+    /// The archive being appended to could not be read
+    CouldNotReadArchive(PlacedArchiveError),
+    CouldNotReconstructCanvas(CanvasReconstructionError),
+}
+
+impl From<mla::errors::Error> for PlacedArchiveWriteError {
+    fn from(err: mla::errors::Error) -> Self {
+        PlacedArchiveWriteError::MLAWriteError(err)
+    }
+}
+
+impl From<std::io::Error> for PlacedArchiveWriteError {
+    fn from(err: std::io::Error) -> Self {
+        PlacedArchiveWriteError::IOError(err)
+    }
+}
+
+impl From<bincode::error::EncodeError> for PlacedArchiveWriteError {
+    fn from(err: bincode::error::EncodeError) -> Self {
+        PlacedArchiveWriteError::CouldNotEncode(err)
+    }
+}
+
+impl From<image::ImageError> for PlacedArchiveWriteError {
+    fn from(err: image::ImageError) -> Self {
+        PlacedArchiveWriteError::CouldNotWriteSnapshot(err)
+    }
 }
 
 #[derive(Debug)]
@@ -10,4 +69,119 @@ pub enum NextTileChunkError {
     OutOfChunks,
     MissingChunkFile,
     CouldNotFetchChunkFile(mla::errors::Error),
+    CouldNotDecodeChunkFile,
+}
+
+#[derive(Debug)]
+pub enum CanvasReconstructionError {
+    CouldNotFetchSnapshotFile(mla::errors::Error),
+    CouldNotDecodeSnapshot(image::ImageError),
+    CouldNotReadTiles(std::io::Error),
+}
+
+#[derive(Debug)]
+pub enum PixelHistoryError {
+    CouldNotFetchIndexFile(mla::errors::Error),
+    CouldNotDecodeIndexFile,
+    CouldNotLoadFootprint(PlacedArchiveError),
+    CouldNotReadTiles(std::io::Error),
+}
+
+/// Problem found by `PlacedArchiveReader::verify()`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum VerificationError {
+    /// Chunks are expected to be numbered consecutively, starting at zero
+    UnexpectedChunkId {
+        expected: u32,
+        found: u32,
+    },
+    MissingChunk(u32),
+    /// The chunk's tile file could not be read or decoded, e.g. because it was cut short
+    UnreadableChunk(u32),
+    ChecksumMismatch(u32),
+    TileCountMismatch {
+        chunk_id: u32,
+        expected: u64,
+        found: u64,
+    },
+    TotalTileCountMismatch {
+        expected: u64,
+        found: u64,
+    },
+    /// A tile in the chunk was placed before the one preceding it, or after the chunk's end
+    UnorderedTiles(u32),
+    UnknownColor {
+        chunk_id: u32,
+        color_index: u16,
+    },
+}
+
+/// Error yielded by `PlacedArchiveReader::try_iter()`, holding the id of the chunk being read.
+#[derive(Debug)]
+pub enum ReadError {
+    /// Only returned when an archive is opened again to read chunks independently, e.g. by `par_map_chunks()`
+    CouldNotOpenArchive(PlacedArchiveError),
+    MissingChunk(u32),
+    CouldNotFetchChunk(u32, mla::errors::Error),
+    CouldNotDecodeChunk(u32),
+    /// The chunk ends partway through a tile
+    TruncatedRecord(u32),
+    UnknownColor {
+        chunk_id: u32,
+        color_index: u16,
+    },
+    /// The thread reading the chunk exited before returning it, e.g. because it panicked
+    ReaderThreadExited(u32),
+}
+
+#[derive(Debug)]
+pub enum MappedArchiveError {
+    CouldNotMapFile(std::io::Error),
+    CouldNotReadArchive(PlacedArchiveError),
+    /// Only archives without compression or encryption can be mapped
+    UnsupportedLayers,
+    /// Only chunks of fixed-width tiles in the current layout can be mapped
+    UnsupportedTileLayout,
+    MalformedArchive,
+    MissingChunkFile(u32),
+    /// The chunk's tile file isn't stored in one piece
+    FragmentedChunkFile(u32),
+    TruncatedChunkFile(u32),
+    /// The chunk's tile file doesn't hold as many tiles as its `ChunkDescription` says
+    TileCountMismatch(u32),
+}
+
+impl From<PlacedArchiveError> for ReadError {
+    fn from(err: PlacedArchiveError) -> Self {
+        ReadError::CouldNotOpenArchive(err)
+    }
+}
+
+impl From<NextTileChunkError> for std::io::Error {
+    fn from(err: NextTileChunkError) -> Self {
+        match err {
+            NextTileChunkError::OutOfChunks => std::io::Error::other("Out of chunks"),
+            NextTileChunkError::MissingChunkFile => std::io::Error::other("Missing chunk file"),
+            NextTileChunkError::CouldNotFetchChunkFile(err) => err.into(),
+            NextTileChunkError::CouldNotDecodeChunkFile => {
+                std::io::Error::other("Could not decode chunk file")
+            }
+        }
+    }
+}
+
+impl From<ReadError> for std::io::Error {
+    fn from(err: ReadError) -> Self {
+        match err {
+            ReadError::CouldNotOpenArchive(_) => std::io::Error::other("Could not open archive"),
+            ReadError::MissingChunk(_) => std::io::Error::other("Missing chunk file"),
+            ReadError::CouldNotFetchChunk(_, err) => err.into(),
+            ReadError::CouldNotDecodeChunk(_) => {
+                std::io::Error::other("Could not decode chunk file")
+            }
+            ReadError::TruncatedRecord(_) => std::io::Error::other("Truncated tile"),
+            ReadError::UnknownColor { .. } => std::io::Error::other("Unknown color"),
+            ReadError::ReaderThreadExited(_) => std::io::Error::other("Reader thread exited"),
+        }
+    }
 }
@@ -0,0 +1,156 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufReader, BufWriter, Seek, SeekFrom},
+};
+
+use bincode::{Decode, Encode};
+use chrono::NaiveDateTime;
+use tempfile::tempfile;
+
+use crate::{
+    archive_writer::IntermediateTilePlacement, constants::BINCODE_CONFIG,
+    errors::PlacedArchiveWriteError,
+};
+
+/// On-disk representation of an `IntermediateTilePlacement` inside a spilled run.
+#[derive(Encode, Decode)]
+struct SpilledTilePlacement {
+    x: u16,
+    y: u16,
+    color_index: u16,
+    user_id: Option<u32>,
+    placed_at_secs: i64,
+    placed_at_nsecs: u32,
+    sequence: u64,
+}
+
+impl From<&IntermediateTilePlacement> for SpilledTilePlacement {
+    fn from(tile: &IntermediateTilePlacement) -> Self {
+        SpilledTilePlacement {
+            x: tile.x,
+            y: tile.y,
+            color_index: tile.color_index,
+            user_id: tile.user_id,
+            placed_at_secs: tile.placed_at.timestamp(),
+            placed_at_nsecs: tile.placed_at.timestamp_subsec_nanos(),
+            sequence: tile.sequence,
+        }
+    }
+}
+
+impl From<SpilledTilePlacement> for IntermediateTilePlacement {
+    fn from(tile: SpilledTilePlacement) -> Self {
+        IntermediateTilePlacement {
+            x: tile.x,
+            y: tile.y,
+            color_index: tile.color_index,
+            user_id: tile.user_id,
+            placed_at: NaiveDateTime::from_timestamp_opt(tile.placed_at_secs, tile.placed_at_nsecs)
+                .unwrap(),
+            sequence: tile.sequence,
+        }
+    }
+}
+
+/// Sorts the given tiles and writes them to a temporary file, leaving `tiles` empty.
+pub(crate) fn spill_sorted_run(
+    tiles: &mut Vec<IntermediateTilePlacement>,
+) -> Result<File, PlacedArchiveWriteError> {
+    tiles.sort_unstable();
+
+    let mut writer = BufWriter::new(tempfile()?);
+    for tile in tiles.drain(..) {
+        bincode::encode_into_std_write(
+            SpilledTilePlacement::from(&tile),
+            &mut writer,
+            BINCODE_CONFIG,
+        )?;
+    }
+
+    let mut file = writer.into_inner().map_err(|err| err.into_error())?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+enum SortedRun {
+    InMemory(std::vec::IntoIter<IntermediateTilePlacement>),
+    Spilled {
+        reader: BufReader<File>,
+        num_remaining: u64,
+    },
+}
+
+impl Iterator for SortedRun {
+    type Item = std::io::Result<IntermediateTilePlacement>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SortedRun::InMemory(tiles) => tiles.next().map(Ok),
+            SortedRun::Spilled {
+                reader,
+                num_remaining,
+            } => {
+                if *num_remaining == 0 {
+                    return None;
+                }
+
+                *num_remaining -= 1;
+                let tile: Result<SpilledTilePlacement, _> =
+                    bincode::decode_from_std_read(reader, BINCODE_CONFIG);
+                Some(tile.map(Into::into).map_err(std::io::Error::other))
+            }
+        }
+    }
+}
+
+/// Lazily merges sorted runs (spilled to disk or still in memory) into a single sorted stream.
+pub(crate) struct MergedRuns {
+    runs: Vec<SortedRun>,
+    heap: BinaryHeap<Reverse<(IntermediateTilePlacement, usize)>>,
+}
+
+impl MergedRuns {
+    /// `spilled_runs` are pairs of files created by `spill_sorted_run()` and the number of tiles they contain.
+    pub(crate) fn new(
+        spilled_runs: Vec<(File, u64)>,
+        mut in_memory_tiles: Vec<IntermediateTilePlacement>,
+    ) -> std::io::Result<Self> {
+        in_memory_tiles.sort_unstable();
+
+        let mut runs: Vec<SortedRun> = spilled_runs
+            .into_iter()
+            .map(|(file, num_remaining)| SortedRun::Spilled {
+                reader: BufReader::new(file),
+                num_remaining,
+            })
+            .collect();
+        runs.push(SortedRun::InMemory(in_memory_tiles.into_iter()));
+
+        let mut heap = BinaryHeap::with_capacity(runs.len());
+        for (i, run) in runs.iter_mut().enumerate() {
+            if let Some(tile) = run.next() {
+                heap.push(Reverse((tile?, i)));
+            }
+        }
+
+        Ok(MergedRuns { runs, heap })
+    }
+}
+
+impl Iterator for MergedRuns {
+    type Item = std::io::Result<IntermediateTilePlacement>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((tile, run_index)) = self.heap.pop()?;
+
+        match self.runs[run_index].next() {
+            Some(Ok(next_tile)) => self.heap.push(Reverse((next_tile, run_index))),
+            Some(Err(err)) => return Some(Err(err)),
+            None => {}
+        }
+
+        Some(Ok(tile))
+    }
+}
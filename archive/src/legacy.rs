@@ -0,0 +1,111 @@
+use bincode::{Decode, Encode};
+use std::collections::BTreeMap;
+
+use crate::structures::{
+    CanvasSizeChange, ChunkDescription, ChunkingStrategy, Compression, Meta, StoredTilePlacement,
+    TileEncoding,
+};
+
+/// `Meta` as written by version 0 archives, before chunking and compression were configurable.
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone)]
+pub(crate) struct MetaV0 {
+    pub canvas_size_changes: Vec<CanvasSizeChangeV0>,
+    pub total_tile_placements: u64,
+    pub last_tile_placed_at_ms_since_epoch: u32,
+    /// rgba
+    pub color_id_to_tuple: BTreeMap<u8, [u8; 4]>,
+    pub chunk_descs: Vec<ChunkDescriptionV0>,
+}
+
+impl From<MetaV0> for Meta {
+    fn from(meta: MetaV0) -> Self {
+        // Version 0 split tiles into a fixed number of equally sized chunks, and wrote archives without any MLA layers
+        let chunking_strategy = match meta.chunk_descs.first() {
+            Some(chunk_desc) => ChunkingStrategy::TileCount(chunk_desc.num_tiles),
+            None => ChunkingStrategy::default(),
+        };
+
+        Meta {
+            first_tile_placed_at_ms_since_unix_epoch: None,
+            canvas_size_changes: meta
+                .canvas_size_changes
+                .into_iter()
+                .map(CanvasSizeChange::from)
+                .collect(),
+            chunking_strategy,
+            compression: Compression::None,
+            tile_encoding: TileEncoding::Fixed,
+            total_tile_placements: meta.total_tile_placements,
+            last_tile_placed_at_ms_since_epoch: meta.last_tile_placed_at_ms_since_epoch as u64,
+            color_id_to_tuple: meta
+                .color_id_to_tuple
+                .into_iter()
+                .map(|(id, color)| (id as u16, color))
+                .collect(),
+            chunk_descs: meta
+                .chunk_descs
+                .into_iter()
+                .map(ChunkDescription::from)
+                .collect(),
+            num_users: None,
+            moderator_edits: Vec::new(),
+        }
+    }
+}
+
+/// `CanvasSizeChange` as written by version 0 archives, with a 32-bit timestamp.
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone)]
+pub(crate) struct CanvasSizeChangeV0 {
+    pub width: u16,
+    pub height: u16,
+    pub ms_since_epoch: u32,
+}
+
+impl From<CanvasSizeChangeV0> for CanvasSizeChange {
+    fn from(change: CanvasSizeChangeV0) -> Self {
+        CanvasSizeChange {
+            width: change.width,
+            height: change.height,
+            ms_since_epoch: change.ms_since_epoch as u64,
+        }
+    }
+}
+
+/// `ChunkDescription` as written by version 0 archives, with a 32-bit timestamp.
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone)]
+pub(crate) struct ChunkDescriptionV0 {
+    pub id: u32,
+    pub up_to_ms_since_epoch: u32,
+    pub num_tiles: u32,
+}
+
+impl From<ChunkDescriptionV0> for ChunkDescription {
+    fn from(chunk_desc: ChunkDescriptionV0) -> Self {
+        ChunkDescription {
+            id: chunk_desc.id,
+            up_to_ms_since_epoch: chunk_desc.up_to_ms_since_epoch as u64,
+            num_tiles: chunk_desc.num_tiles,
+            checksum: None,
+        }
+    }
+}
+
+/// `StoredTilePlacement` as written by version 0 archives, with an 8-bit color index and a 32-bit timestamp.
+#[derive(Encode, Decode, PartialEq, Eq, Debug)]
+pub(crate) struct StoredTilePlacementV0 {
+    pub x: u16,
+    pub y: u16,
+    pub color_index: u8,
+    pub ms_since_epoch: u32,
+}
+
+impl From<StoredTilePlacementV0> for StoredTilePlacement {
+    fn from(tile: StoredTilePlacementV0) -> Self {
+        StoredTilePlacement {
+            x: tile.x,
+            y: tile.y,
+            color_index: tile.color_index as u16,
+            ms_since_epoch: tile.ms_since_epoch as u64,
+        }
+    }
+}
@@ -1,8 +1,26 @@
 mod archive_reader;
 mod archive_writer;
+#[cfg(feature = "async")]
+mod async_reader;
+mod chunk_tiles;
 mod constants;
-mod errors;
+pub mod errors;
+mod external_sort;
+mod legacy;
+mod mapped_archive;
+mod mla_layout;
+mod parallel;
+mod pixel_index;
+mod shared_archive;
 pub mod structures;
+mod tile_encoding;
 
-pub use crate::archive_reader::PlacedArchiveReader;
-pub use crate::archive_writer::PlacedArchiveWriter;
+pub use crate::archive_reader::{PlacedArchiveReader, ReverseTiles, TryTiles};
+pub use crate::archive_writer::{PlacedArchiveWriter, PlacedArchiveWriterOptions};
+#[cfg(feature = "async")]
+pub use crate::async_reader::{AsyncPlacedArchiveReader, TileStream};
+pub use crate::constants::FORMAT_VERSION;
+pub use crate::mapped_archive::{MappedArchive, MappedTilePlacement};
+pub use crate::parallel::par_map_chunks;
+pub use crate::shared_archive::{ArchiveCursor, PlacedArchive, PlacedArchiveOptions};
+pub use x25519_dalek::{PublicKey, StaticSecret};
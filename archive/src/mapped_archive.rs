@@ -0,0 +1,274 @@
+use std::{fs::File, io::Cursor, ops::Range};
+
+use memmap2::Mmap;
+
+use crate::{
+    errors::MappedArchiveError,
+    mla_layout::{self, MlaLayoutError},
+    structures::{Meta, StoredTilePlacement, TileEncoding},
+    PlacedArchiveReader,
+};
+
+/// Tile placement as stored in a fixed-width chunk, which can be viewed in place since it has no alignment requirements.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[repr(C)]
+pub struct MappedTilePlacement {
+    x: [u8; 2],
+    y: [u8; 2],
+    color_index: [u8; 2],
+    ms_since_epoch: [u8; 8],
+}
+
+impl MappedTilePlacement {
+    pub fn x(&self) -> u16 {
+        u16::from_le_bytes(self.x)
+    }
+
+    pub fn y(&self) -> u16 {
+        u16::from_le_bytes(self.y)
+    }
+
+    pub fn color_index(&self) -> u16 {
+        u16::from_le_bytes(self.color_index)
+    }
+
+    pub fn ms_since_epoch(&self) -> u64 {
+        u64::from_le_bytes(self.ms_since_epoch)
+    }
+}
+
+impl From<&MappedTilePlacement> for StoredTilePlacement {
+    fn from(tile: &MappedTilePlacement) -> Self {
+        StoredTilePlacement {
+            x: tile.x(),
+            y: tile.y(),
+            color_index: tile.color_index(),
+            ms_since_epoch: tile.ms_since_epoch(),
+        }
+    }
+}
+
+/// Memory-mapped archive, exposing each chunk as a slice of the file instead of copying and decoding it.
+/// Only archives written without compression or encryption, using `TileEncoding::Fixed`, can be mapped.
+pub struct MappedArchive {
+    mmap: Mmap,
+    /// Format version the archive was written with, see `FORMAT_VERSION`
+    pub format_version: u32,
+    pub meta: Meta,
+    // Location of each chunk's tile file within the mapped file, indexed by chunk id
+    chunk_ranges: Vec<Range<usize>>,
+}
+
+impl MappedArchive {
+    /// Maps `file`, which must not be modified while the archive is in use.
+    pub fn open(file: &File) -> Result<Self, MappedArchiveError> {
+        // Safety: archives aren't written to once they're finalized
+        let mmap = unsafe { Mmap::map(file) }.map_err(MappedArchiveError::CouldNotMapFile)?;
+
+        let (format_version, meta) = {
+            let reader = PlacedArchiveReader::new(Cursor::new(&mmap[..]))
+                .map_err(MappedArchiveError::CouldNotReadArchive)?;
+            (reader.format_version, reader.meta)
+        };
+
+        // Version 0 archives store tiles in a legacy layout
+        if format_version == 0 || meta.tile_encoding != TileEncoding::Fixed {
+            return Err(MappedArchiveError::UnsupportedTileLayout);
+        }
+
+        let encoded_size = StoredTilePlacement::encoded_size();
+        if std::mem::size_of::<MappedTilePlacement>() != encoded_size {
+            return Err(MappedArchiveError::UnsupportedTileLayout);
+        }
+
+        let mut src = Cursor::new(&mmap[..]);
+        let files = mla_layout::read_header(&mut src)
+            .and_then(|header_len| mla_layout::read_index(&mut src, header_len))
+            .map_err(|err| match err {
+                MlaLayoutError::UnsupportedLayers => MappedArchiveError::UnsupportedLayers,
+                MlaLayoutError::Malformed => MappedArchiveError::MalformedArchive,
+            })?;
+
+        let mut chunk_ranges = Vec::with_capacity(meta.chunk_descs.len());
+        for chunk_desc in &meta.chunk_descs {
+            let location = files
+                .get(&format!("tiles/{}", chunk_desc.id))
+                .ok_or(MappedArchiveError::MissingChunkFile(chunk_desc.id))?;
+            let file_range = location.range();
+            let ranges = mmap
+                .get(file_range.start as usize..file_range.end as usize)
+                .ok_or(MlaLayoutError::Malformed)
+                .and_then(|data| mla_layout::file_content_ranges(data, file_range.start, location))
+                .map_err(|_| MappedArchiveError::MalformedArchive)?;
+
+            let range = match &ranges[..] {
+                [] => 0..0,
+                [range] => {
+                    file_range.start as usize + range.start..file_range.start as usize + range.end
+                }
+                _ => return Err(MappedArchiveError::FragmentedChunkFile(chunk_desc.id)),
+            };
+            if range.len() % encoded_size != 0 {
+                return Err(MappedArchiveError::TruncatedChunkFile(chunk_desc.id));
+            }
+            if range.len() / encoded_size != chunk_desc.num_tiles as usize {
+                return Err(MappedArchiveError::TileCountMismatch(chunk_desc.id));
+            }
+
+            chunk_ranges.push(range);
+        }
+
+        Ok(MappedArchive {
+            mmap,
+            format_version,
+            meta,
+            chunk_ranges,
+        })
+    }
+
+    /// Returns the tiles of a chunk, without copying them.
+    pub fn chunk(&self, chunk_id: u32) -> Option<&[MappedTilePlacement]> {
+        let bytes = self.chunk_bytes(chunk_id)?;
+
+        // Safety: `MappedTilePlacement` consists of byte arrays only, so it has no alignment requirements and is valid for
+        // any bytes. `open()` checked that it has the same size as an encoded `StoredTilePlacement`, and that the chunk
+        // consists of whole tiles
+        Some(unsafe {
+            std::slice::from_raw_parts(
+                bytes.as_ptr() as *const MappedTilePlacement,
+                bytes.len() / std::mem::size_of::<MappedTilePlacement>(),
+            )
+        })
+    }
+
+    /// Returns the tile file of a chunk, a sequence of encoded `StoredTilePlacement`s, e.g. to upload it to the GPU as is.
+    pub fn chunk_bytes(&self, chunk_id: u32) -> Option<&[u8]> {
+        let range = self.chunk_ranges.get(chunk_id as usize)?;
+
+        Some(&self.mmap[range.clone()])
+    }
+
+    /// Iterates over every tile in the archive, in the order they were placed.
+    pub fn tiles(&self) -> impl Iterator<Item = &MappedTilePlacement> {
+        (0..self.chunk_ranges.len() as u32).flat_map(|chunk_id| self.chunk(chunk_id).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+    use tempfile::NamedTempFile;
+
+    use crate::{
+        constants::BINCODE_CONFIG,
+        errors::MappedArchiveError,
+        structures::{ChunkingStrategy, Compression, StoredTilePlacement},
+        PlacedArchiveReader, PlacedArchiveWriter, PlacedArchiveWriterOptions,
+    };
+
+    use super::MappedArchive;
+
+    fn write_archive(options: PlacedArchiveWriterOptions) -> std::fs::File {
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut archive_writer =
+            PlacedArchiveWriter::with_options(writeable_file, options).unwrap();
+        for i in 0..250 {
+            archive_writer
+                .add_tile(
+                    i % 100,
+                    i / 100,
+                    [(i % 3) as u8, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(i as i64).unwrap(),
+                )
+                .unwrap();
+        }
+        archive_writer.finalize().unwrap();
+
+        readable_file
+    }
+
+    #[test]
+    fn maps_uncompressed_archive() {
+        let file = write_archive(PlacedArchiveWriterOptions {
+            chunking_strategy: ChunkingStrategy::TileCount(100),
+            generate_snapshots: true,
+            ..Default::default()
+        });
+        let archive = MappedArchive::open(&file).unwrap();
+        let reader = PlacedArchiveReader::new(file).unwrap();
+
+        assert_eq!(archive.meta, reader.meta);
+        assert_eq!(archive.chunk(0).unwrap().len(), 100);
+        assert_eq!(archive.chunk(2).unwrap().len(), 50);
+        assert_eq!(archive.chunk(3), None);
+        assert_eq!(
+            archive.chunk_bytes(1).unwrap().len(),
+            100 * StoredTilePlacement::encoded_size()
+        );
+
+        let colors = archive.meta.color_id_to_tuple.clone();
+        assert!(archive
+            .tiles()
+            .map(|tile| (
+                tile.x(),
+                tile.y(),
+                tile.ms_since_epoch(),
+                colors[&tile.color_index()]
+            ))
+            .eq(reader.map(|tile| (tile.x, tile.y, tile.ms_since_epoch, tile.color))));
+    }
+
+    #[test]
+    fn rejects_compressed_archive() {
+        let file = write_archive(PlacedArchiveWriterOptions {
+            compression: Compression::Brotli(5),
+            ..Default::default()
+        });
+
+        assert!(matches!(
+            MappedArchive::open(&file),
+            Err(MappedArchiveError::UnsupportedLayers)
+        ));
+    }
+
+    #[test]
+    fn rejects_chunk_with_wrong_tile_count() {
+        let file = write_archive(PlacedArchiveWriterOptions {
+            chunking_strategy: ChunkingStrategy::TileCount(100),
+            ..Default::default()
+        });
+        let mut reader = PlacedArchiveReader::new(file).unwrap();
+        let mut meta = reader.meta.clone();
+        meta.chunk_descs[1].num_tiles = 99;
+
+        // Copy the archive with the altered meta
+        let writeable_file = NamedTempFile::new().unwrap();
+        let readable_file = writeable_file.reopen().unwrap();
+        let mut config = mla::config::ArchiveWriterConfig::new();
+        config.set_layers(mla::Layers::EMPTY);
+        let mut mla = mla::ArchiveWriter::from_config(writeable_file, config).unwrap();
+        let file_names = reader
+            .mla
+            .list_files()
+            .unwrap()
+            .cloned()
+            .collect::<Vec<_>>();
+        for file_name in file_names {
+            if file_name == "meta" {
+                let meta_buf = bincode::encode_to_vec(&meta, BINCODE_CONFIG).unwrap();
+                mla.add_file("meta", meta_buf.len() as u64, meta_buf.as_slice())
+                    .unwrap();
+            } else {
+                let file = reader.mla.get_file(file_name.clone()).unwrap().unwrap();
+                mla.add_file(&file_name, file.size, file.data).unwrap();
+            }
+        }
+        mla.finalize().unwrap();
+
+        assert!(matches!(
+            MappedArchive::open(&readable_file),
+            Err(MappedArchiveError::TileCountMismatch(1))
+        ));
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom},
+    ops::Range,
+};
+
+use mla::ArchiveHeader;
+
+// Tags of the blocks MLA splits files into
+const FILE_START_BLOCK: u8 = 0x00;
+const FILE_CONTENT_BLOCK: u8 = 0x01;
+const END_OF_FILE_BLOCK: u8 = 0xFF;
+
+/// Size of the block ending a file: its tag, the file's id and its SHA-256 hash.
+const END_OF_FILE_BLOCK_SIZE: u64 = 1 + 8 + 32;
+
+/// Problem reading the layout of an MLA archive directly, see `read_header()`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum MlaLayoutError {
+    /// Files in archives with compression or encryption can't be located in the underlying bytes
+    UnsupportedLayers,
+    Malformed,
+}
+
+/// Where a file is stored in an archive without layers, as recorded in the archive's index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FileLocation {
+    /// Offsets of the runs of consecutive blocks the file is split into, from the start of the archive
+    pub block_offsets: Vec<u64>,
+    /// Offset of the block ending the file, from the start of the archive
+    pub end_of_file_offset: u64,
+    pub size: u64,
+}
+
+impl FileLocation {
+    /// Range of the archive holding every block of the file, which may be interleaved with blocks of other files.
+    pub fn range(&self) -> Range<u64> {
+        let start = self
+            .block_offsets
+            .iter()
+            .copied()
+            .min()
+            .unwrap_or(self.end_of_file_offset);
+
+        start..self.end_of_file_offset + END_OF_FILE_BLOCK_SIZE
+    }
+}
+
+/// Reads the header of an MLA archive, returning its length. Fails if the archive has any layers.
+///
+/// Reading archives through their layout instead of `mla::ArchiveReader` relies on MLA's on-disk format, which is why
+/// the `mla` dependency is pinned to an exact version. `tests::matches_mla` checks the layout against that version.
+pub(crate) fn read_header(src: &mut (impl Read + Seek)) -> Result<u64, MlaLayoutError> {
+    src.seek(SeekFrom::Start(0))
+        .map_err(|_| MlaLayoutError::Malformed)?;
+    let header = ArchiveHeader::from(src).map_err(|_| MlaLayoutError::Malformed)?;
+    if !header.config.layers_enabled.is_empty() {
+        return Err(MlaLayoutError::UnsupportedLayers);
+    }
+
+    src.stream_position().map_err(|_| MlaLayoutError::Malformed)
+}
+
+/// Reads the index at the end of an archive without layers, whose header is `header_len` bytes long.
+pub(crate) fn read_index(
+    src: &mut (impl Read + Seek),
+    header_len: u64,
+) -> Result<HashMap<String, FileLocation>, MlaLayoutError> {
+    read_index_entries(src, header_len).ok_or(MlaLayoutError::Malformed)
+}
+
+fn read_index_entries(
+    src: &mut (impl Read + Seek),
+    header_len: u64,
+) -> Option<HashMap<String, FileLocation>> {
+    fn read_u64(src: &mut impl Read) -> Option<u64> {
+        let mut bytes = [0; 8];
+        src.read_exact(&mut bytes).ok()?;
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    // The index is a map from file names to locations encoded with bincode 1's fixed-width integers, followed by its length
+    let index_end = src.seek(SeekFrom::End(-4)).ok()?;
+    let mut len_bytes = [0; 4];
+    src.read_exact(&mut len_bytes).ok()?;
+    let index_len = u32::from_le_bytes(len_bytes) as u64;
+    src.seek(SeekFrom::Start(index_end.checked_sub(index_len)?))
+        .ok()?;
+    let mut index = src.take(index_len);
+
+    // Offsets in the index start after the header
+    let num_files = read_u64(&mut index)?;
+    let mut files = HashMap::new();
+    for _ in 0..num_files {
+        let name_len = read_u64(&mut index)?;
+        let mut name = Vec::new();
+        (&mut index).take(name_len).read_to_end(&mut name).ok()?;
+        if name.len() as u64 != name_len {
+            return None;
+        }
+
+        let num_offsets = read_u64(&mut index)?;
+        let mut block_offsets = Vec::new();
+        for _ in 0..num_offsets {
+            block_offsets.push(header_len.checked_add(read_u64(&mut index)?)?);
+        }
+        let size = read_u64(&mut index)?;
+        let end_of_file_offset = header_len.checked_add(read_u64(&mut index)?)?;
+
+        files.insert(
+            String::from_utf8(name).ok()?,
+            FileLocation {
+                block_offsets,
+                end_of_file_offset,
+                size,
+            },
+        );
+    }
+
+    Some(files)
+}
+
+/// Finds the contents of a file, given `data` holding the archive from `data_offset` on, up to at least the end of
+/// `location.range()`. Returns the ranges of `data` the file's content is split into, in order.
+pub(crate) fn file_content_ranges(
+    data: &[u8],
+    data_offset: u64,
+    location: &FileLocation,
+) -> Result<Vec<Range<usize>>, MlaLayoutError> {
+    let read_u64 = |pos: usize| -> Result<u64, MlaLayoutError> {
+        data.get(pos..pos + 8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or(MlaLayoutError::Malformed)
+    };
+    let position = |offset: u64| -> Result<usize, MlaLayoutError> {
+        offset
+            .checked_sub(data_offset)
+            .map(|pos| pos as usize)
+            .ok_or(MlaLayoutError::Malformed)
+    };
+
+    let mut file_id = None;
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for &block_offset in &location.block_offsets {
+        // Each run of blocks ends where a block of another file starts
+        let mut pos = position(block_offset)?;
+        loop {
+            let tag = *data.get(pos).ok_or(MlaLayoutError::Malformed)?;
+            let id = read_u64(pos + 1)?;
+            if tag == FILE_START_BLOCK && file_id.is_none() {
+                file_id = Some(id);
+            }
+            if file_id != Some(id) {
+                break;
+            }
+
+            match tag {
+                FILE_START_BLOCK => pos += 17 + read_u64(pos + 9)? as usize,
+                FILE_CONTENT_BLOCK => {
+                    let len = read_u64(pos + 9)? as usize;
+                    let content = pos + 17..pos + 17 + len;
+                    if content.end > data.len() {
+                        return Err(MlaLayoutError::Malformed);
+                    }
+
+                    match ranges.last_mut() {
+                        Some(range) if range.end == content.start => range.end = content.end,
+                        _ => ranges.push(content.clone()),
+                    }
+                    pos = content.end;
+                }
+                _ => break,
+            }
+        }
+    }
+
+    let eof_pos = position(location.end_of_file_offset)?;
+    let content_len = ranges.iter().map(|range| range.len() as u64).sum::<u64>();
+    if data.get(eof_pos) != Some(&END_OF_FILE_BLOCK)
+        || file_id != Some(read_u64(eof_pos + 1)?)
+        || content_len != location.size
+    {
+        return Err(MlaLayoutError::Malformed);
+    }
+
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use mla::{config::ArchiveWriterConfig, ArchiveReader, ArchiveWriter, Layers};
+
+    use super::{file_content_ranges, read_header, read_index, MlaLayoutError};
+
+    fn write_archive(layers: Layers) -> Vec<u8> {
+        let mut config = ArchiveWriterConfig::new();
+        config.set_layers(layers);
+        let mut archive = ArchiveWriter::from_config(Vec::new(), config).unwrap();
+
+        // Interleaved files, split into several runs of blocks
+        let first = archive.start_file("first").unwrap();
+        let second = archive.start_file("second").unwrap();
+        for i in 0..3u8 {
+            let content = vec![i; 1000 + i as usize];
+            archive
+                .append_file_content(first, content.len() as u64, content.as_slice())
+                .unwrap();
+            archive
+                .append_file_content(first, 10, &content[..10])
+                .unwrap();
+            archive
+                .append_file_content(second, 5, &content[..5])
+                .unwrap();
+        }
+        archive.end_file(first).unwrap();
+        archive.add_file("empty", 0, &[][..]).unwrap();
+        archive.end_file(second).unwrap();
+        archive.finalize().unwrap();
+
+        archive.into_raw()
+    }
+
+    #[test]
+    fn matches_mla() {
+        let data = write_archive(Layers::EMPTY);
+        let mut src = Cursor::new(&data[..]);
+        let header_len = read_header(&mut src).unwrap();
+        let index = read_index(&mut src, header_len).unwrap();
+
+        let mut mla = ArchiveReader::new(Cursor::new(&data[..])).unwrap();
+        let mut file_names = mla.list_files().unwrap().cloned().collect::<Vec<_>>();
+        file_names.sort();
+        let mut index_names = index.keys().cloned().collect::<Vec<_>>();
+        index_names.sort();
+        assert_eq!(index_names, file_names);
+
+        for file_name in file_names {
+            let location = &index[&file_name];
+            let range = location.range();
+            let data_in_range = &data[range.start as usize..range.end as usize];
+            let content = file_content_ranges(data_in_range, range.start, location)
+                .unwrap()
+                .into_iter()
+                .flat_map(|range| data_in_range[range].to_vec())
+                .collect::<Vec<_>>();
+
+            let mut expected = Vec::new();
+            let mut file = mla.get_file(file_name).unwrap().unwrap();
+            file.data.read_to_end(&mut expected).unwrap();
+            assert_eq!(content, expected);
+        }
+    }
+
+    #[test]
+    fn rejects_layers() {
+        let data = write_archive(Layers::COMPRESS);
+
+        assert_eq!(
+            read_header(&mut Cursor::new(&data[..])),
+            Err(MlaLayoutError::UnsupportedLayers)
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    io::{Read, Seek},
+    num::NonZeroUsize,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+use crate::{
+    errors::{PlacedArchiveError, ReadError},
+    structures::DecodedTilePlacement,
+    PlacedArchiveReader,
+};
+
+/// Reads the chunks of an archive on `num_threads` threads and runs `f` on the tiles of each one, e.g. to count colors or
+/// build a heatmap per chunk. Every thread reads its own copy of the archive, as opened by `open_archive`.
+/// Returns the results in chunk order, so they can be merged in the order the tiles were placed.
+/// A thread panicking while reading a chunk or running `f` on it is reported as `ReadError::ReaderThreadExited`.
+pub fn par_map_chunks<'a, R, T, O, F>(
+    num_threads: NonZeroUsize,
+    open_archive: O,
+    f: F,
+) -> Result<Vec<T>, ReadError>
+where
+    R: Read + Seek + 'a,
+    T: Send,
+    O: Fn() -> Result<PlacedArchiveReader<'a, R>, PlacedArchiveError> + Sync,
+    F: Fn(u32, Vec<DecodedTilePlacement>) -> T + Sync,
+{
+    let next_chunk_id = AtomicU32::new(0);
+    let failed = AtomicBool::new(false);
+    // Chunk each thread is reading, to report which one was being read if the thread panics
+    let current_chunk_ids = (0..num_threads.get())
+        .map(|_| AtomicU32::new(0))
+        .collect::<Vec<_>>();
+
+    let mut results = std::thread::scope(|scope| {
+        let workers = current_chunk_ids
+            .iter()
+            .map(|current_chunk_id| {
+                let next_chunk_id = &next_chunk_id;
+                let failed = &failed;
+                let open_archive = &open_archive;
+                let f = &f;
+                scope.spawn(move || {
+                    let mut results = Vec::new();
+                    let mut reader = match open_archive() {
+                        Ok(reader) => reader,
+                        Err(err) => {
+                            failed.store(true, Ordering::Relaxed);
+                            // Sorts before the results of every chunk
+                            results.push((0, Err(ReadError::CouldNotOpenArchive(err))));
+                            return results;
+                        }
+                    };
+
+                    while !failed.load(Ordering::Relaxed) {
+                        let chunk_id = next_chunk_id.fetch_add(1, Ordering::Relaxed);
+                        if chunk_id as usize >= reader.meta.chunk_descs.len() {
+                            break;
+                        }
+                        current_chunk_id.store(chunk_id, Ordering::Relaxed);
+
+                        let result = reader.read_chunk(chunk_id).map(|tiles| f(chunk_id, tiles));
+                        if result.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        results.push((chunk_id, result));
+                    }
+
+                    results
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .zip(&current_chunk_ids)
+            .flat_map(|(worker, current_chunk_id)| {
+                worker.join().unwrap_or_else(|_| {
+                    let chunk_id = current_chunk_id.load(Ordering::Relaxed);
+                    vec![(chunk_id, Err(ReadError::ReaderThreadExited(chunk_id)))]
+                })
+            })
+            .collect::<Vec<_>>()
+    });
+
+    results.sort_by_key(|(chunk_id, result)| (*chunk_id, result.is_ok()));
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, fs::File, num::NonZeroUsize};
+
+    use chrono::NaiveDateTime;
+    use tempfile::NamedTempFile;
+
+    use crate::{
+        errors::{PlacedArchiveError, ReadError},
+        structures::{ChunkingStrategy, Compression},
+        PlacedArchiveReader, PlacedArchiveWriter, PlacedArchiveWriterOptions,
+    };
+
+    use super::par_map_chunks;
+
+    #[test]
+    fn matches_sequential_reading() {
+        let file = NamedTempFile::new().unwrap();
+        let mut archive_writer = PlacedArchiveWriter::with_options(
+            file.reopen().unwrap(),
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(64),
+                compression: Compression::Brotli(1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for i in 0..1000u32 {
+            archive_writer
+                .add_tile(
+                    (i * 7 % 50) as u16,
+                    (i * 13 % 50) as u16,
+                    [(i % 5) as u8, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(i as i64).unwrap(),
+                )
+                .unwrap();
+        }
+        archive_writer.finalize().unwrap();
+
+        let open_archive = || PlacedArchiveReader::new(File::open(file.path()).unwrap());
+        let color_counts =
+            par_map_chunks(NonZeroUsize::new(4).unwrap(), open_archive, |_, tiles| {
+                let mut counts = BTreeMap::new();
+                for tile in tiles {
+                    *counts.entry(tile.color).or_insert(0) += 1;
+                }
+                counts
+            })
+            .unwrap();
+        assert_eq!(color_counts.len(), 16);
+
+        let mut merged_counts = BTreeMap::new();
+        for (color, count) in color_counts.into_iter().flatten() {
+            *merged_counts.entry(color).or_insert(0) += count;
+        }
+        let mut expected_counts = BTreeMap::new();
+        for tile in open_archive().unwrap() {
+            *expected_counts.entry(tile.color).or_insert(0) += 1;
+        }
+        assert_eq!(merged_counts, expected_counts);
+
+        let tiles = par_map_chunks(NonZeroUsize::new(3).unwrap(), open_archive, |_, tiles| {
+            tiles
+        })
+        .unwrap();
+        assert!(tiles.into_iter().flatten().eq(open_archive().unwrap()));
+    }
+
+    #[test]
+    fn reports_unreadable_archive() {
+        let file = NamedTempFile::new().unwrap();
+
+        let result = par_map_chunks(
+            NonZeroUsize::new(2).unwrap(),
+            || PlacedArchiveReader::new(File::open(file.path()).unwrap()),
+            |_, tiles| tiles.len(),
+        );
+        assert!(matches!(
+            result,
+            Err(ReadError::CouldNotOpenArchive(
+                PlacedArchiveError::MLAReadError(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn reports_panicking_thread() {
+        let file = NamedTempFile::new().unwrap();
+        let mut archive_writer = PlacedArchiveWriter::with_options(
+            file.reopen().unwrap(),
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(10),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for i in 0..100u32 {
+            archive_writer
+                .add_tile(
+                    i as u16,
+                    0,
+                    [0, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(i as i64).unwrap(),
+                )
+                .unwrap();
+        }
+        archive_writer.finalize().unwrap();
+
+        let result = par_map_chunks(
+            NonZeroUsize::new(2).unwrap(),
+            || PlacedArchiveReader::new(File::open(file.path()).unwrap()),
+            |chunk_id, tiles| {
+                assert_ne!(chunk_id, 4);
+                tiles.len()
+            },
+        );
+        assert!(matches!(result, Err(ReadError::ReaderThreadExited(4))));
+    }
+}
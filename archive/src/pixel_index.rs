@@ -0,0 +1,39 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use bincode::{Decode, Encode};
+
+use crate::structures::BoundingBox;
+
+/// Side length of the square cells the canvas is split into by the pixel index.
+const CELL_SIZE: u16 = 64;
+
+/// Spatial index stored in an archive's "pixel_index" file, listing the chunks that place tiles in each cell of a grid over the canvas.
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Default)]
+pub(crate) struct PixelIndex {
+    /// Chunk ids in ascending order, keyed by (row, column) of the cell
+    chunk_ids_by_cell: BTreeMap<(u16, u16), Vec<u32>>,
+}
+
+impl PixelIndex {
+    pub fn insert(&mut self, x: u16, y: u16, chunk_id: u32) {
+        let chunk_ids = self
+            .chunk_ids_by_cell
+            .entry((y / CELL_SIZE, x / CELL_SIZE))
+            .or_default();
+
+        if chunk_ids.last() != Some(&chunk_id) {
+            chunk_ids.push(chunk_id);
+        }
+    }
+
+    /// Returns the ids of the chunks placing tiles in any cell overlapping `region`.
+    pub fn chunk_ids_touching(&self, region: &BoundingBox) -> BTreeSet<u32> {
+        (region.min_y / CELL_SIZE..=region.max_y / CELL_SIZE)
+            .flat_map(|row| {
+                self.chunk_ids_by_cell
+                    .range((row, region.min_x / CELL_SIZE)..=(row, region.max_x / CELL_SIZE))
+            })
+            .flat_map(|(_, chunk_ids)| chunk_ids.iter().copied())
+            .collect()
+    }
+}
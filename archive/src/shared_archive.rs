@@ -0,0 +1,423 @@
+use std::{
+    collections::VecDeque,
+    io::{Read, Seek},
+    sync::{mpsc, Arc, Mutex},
+};
+
+use x25519_dalek::StaticSecret;
+
+use crate::{
+    archive_reader::{decode_chunk_files, ChunkFiles},
+    errors::{PlacedArchiveError, ReadError},
+    structures::{DecodedTilePlacement, Meta},
+    PlacedArchiveReader,
+};
+
+type ChunkRequest = (u32, mpsc::Sender<Result<ChunkFiles, ReadError>>);
+
+#[derive(Clone)]
+pub struct PlacedArchiveOptions {
+    /// Private keys to try if the archive is encrypted.
+    pub private_keys: Vec<StaticSecret>,
+    /// Maximum number of decoded chunks kept in memory, shared between every cursor of the archive.
+    pub chunk_cache_capacity: usize,
+}
+
+impl Default for PlacedArchiveOptions {
+    fn default() -> Self {
+        PlacedArchiveOptions {
+            private_keys: Vec::new(),
+            chunk_cache_capacity: 8,
+        }
+    }
+}
+
+/// Handle to an archive that can be cloned cheaply and shared between threads, to read it from several positions at once
+/// through independent `ArchiveCursor`s. The archive is opened and its `Meta` decoded only once.
+/// MLA readers can't be shared between threads, so the files of chunks are read one at a time on a dedicated thread,
+/// which exits once every handle and cursor has been dropped. Chunks are decoded on the threads requesting them.
+#[derive(Clone)]
+pub struct PlacedArchive {
+    shared: Arc<SharedArchive>,
+}
+
+struct SharedArchive {
+    format_version: u32,
+    meta: Meta,
+    chunk_requests: mpsc::Sender<ChunkRequest>,
+    chunk_cache: Mutex<ChunkCache>,
+}
+
+impl PlacedArchive {
+    pub fn new<R: Read + Seek + Send + 'static>(reader: R) -> Result<Self, PlacedArchiveError> {
+        Self::with_options(reader, PlacedArchiveOptions::default())
+    }
+
+    pub fn with_options<R: Read + Seek + Send + 'static>(
+        reader: R,
+        options: PlacedArchiveOptions,
+    ) -> Result<Self, PlacedArchiveError> {
+        let (opened_sender, opened_receiver) = mpsc::channel();
+        let (chunk_requests, chunk_request_receiver) = mpsc::channel::<ChunkRequest>();
+
+        std::thread::spawn(move || {
+            let mut reader =
+                match PlacedArchiveReader::with_private_keys(reader, &options.private_keys) {
+                    Ok(reader) => reader,
+                    Err(err) => {
+                        let _ = opened_sender.send(Err(err));
+                        return;
+                    }
+                };
+            let _ = opened_sender.send(Ok((reader.format_version, reader.meta.clone())));
+
+            // Ends once every sender, held by the handles, has been dropped
+            for (chunk_id, reply) in chunk_request_receiver {
+                let _ = reply.send(reader.read_chunk_files(chunk_id));
+            }
+        });
+
+        let (format_version, meta) = opened_receiver
+            .recv()
+            .map_err(|_| PlacedArchiveError::ReaderThreadExited)??;
+
+        Ok(PlacedArchive {
+            shared: Arc::new(SharedArchive {
+                format_version,
+                meta,
+                chunk_requests,
+                chunk_cache: Mutex::new(ChunkCache::new(options.chunk_cache_capacity)),
+            }),
+        })
+    }
+
+    /// Format version the archive was written with, see `FORMAT_VERSION`
+    pub fn format_version(&self) -> u32 {
+        self.shared.format_version
+    }
+
+    pub fn meta(&self) -> &Meta {
+        &self.shared.meta
+    }
+
+    /// Creates a cursor positioned at the first tile of the archive.
+    pub fn cursor(&self) -> ArchiveCursor {
+        ArchiveCursor {
+            archive: self.clone(),
+            chunk_id: 0,
+            tile_index_in_chunk: 0,
+            chunk: None,
+            failed: false,
+        }
+    }
+
+    /// Returns every tile of a chunk, from the cache if it has been read recently.
+    pub fn read_chunk(&self, chunk_id: u32) -> Result<Arc<Vec<DecodedTilePlacement>>, ReadError> {
+        if let Some(chunk) = self.shared.chunk_cache.lock().unwrap().get(chunk_id) {
+            return Ok(chunk);
+        }
+
+        let (reply_sender, reply_receiver) = mpsc::channel();
+        self.shared
+            .chunk_requests
+            .send((chunk_id, reply_sender))
+            .map_err(|_| ReadError::ReaderThreadExited(chunk_id))?;
+        let files = reply_receiver
+            .recv()
+            .map_err(|_| ReadError::ReaderThreadExited(chunk_id))??;
+        let chunk = Arc::new(decode_chunk_files(
+            &self.shared.meta,
+            self.shared.format_version,
+            chunk_id,
+            files,
+        )?);
+
+        self.shared
+            .chunk_cache
+            .lock()
+            .unwrap()
+            .insert(chunk_id, chunk.clone());
+
+        Ok(chunk)
+    }
+}
+
+/// Position in a `PlacedArchive`, iterating over its tiles independently of other cursors. Created by
+/// `PlacedArchive::cursor()`. Yields an error instead of ending early if the archive is broken, and ends after it.
+pub struct ArchiveCursor {
+    archive: PlacedArchive,
+    chunk_id: u32,
+    tile_index_in_chunk: usize,
+    // Chunk the cursor is in, loaded when the next tile is requested
+    chunk: Option<Arc<Vec<DecodedTilePlacement>>>,
+    failed: bool,
+}
+
+impl ArchiveCursor {
+    /// Moves the cursor to the tile at `tile_index`, counting from the first tile in the archive.
+    pub fn seek_to_tile_index(&mut self, tile_index: u64) {
+        let mut first_tile_index_in_chunk = 0;
+        let mut chunk_id = 0;
+        for chunk_desc in &self.archive.meta().chunk_descs {
+            if first_tile_index_in_chunk + chunk_desc.num_tiles as u64 > tile_index {
+                break;
+            }
+
+            first_tile_index_in_chunk += chunk_desc.num_tiles as u64;
+            chunk_id += 1;
+        }
+
+        self.move_to(chunk_id, (tile_index - first_tile_index_in_chunk) as usize);
+    }
+
+    /// Moves the cursor to the first tile placed at or after `ms_since_epoch` and returns its index.
+    /// If every tile was placed before `ms_since_epoch`, the cursor is moved to the end of the archive.
+    pub fn seek_to_ms(&mut self, ms_since_epoch: u64) -> Result<u64, ReadError> {
+        let chunk_descs = &self.archive.meta().chunk_descs;
+        let chunk_id =
+            chunk_descs.partition_point(|desc| desc.up_to_ms_since_epoch < ms_since_epoch);
+        let first_tile_index_in_chunk = chunk_descs[..chunk_id]
+            .iter()
+            .fold(0, |acc, desc| acc + desc.num_tiles as u64);
+
+        if chunk_id == chunk_descs.len() {
+            self.move_to(chunk_id as u32, 0);
+            return Ok(first_tile_index_in_chunk);
+        }
+
+        let chunk = self.archive.read_chunk(chunk_id as u32)?;
+        let tile_index_in_chunk =
+            chunk.partition_point(|tile| tile.ms_since_epoch < ms_since_epoch);
+        self.move_to(chunk_id as u32, tile_index_in_chunk);
+        self.chunk = Some(chunk);
+
+        Ok(first_tile_index_in_chunk + tile_index_in_chunk as u64)
+    }
+
+    fn move_to(&mut self, chunk_id: u32, tile_index_in_chunk: usize) {
+        if self.chunk_id != chunk_id {
+            self.chunk = None;
+        }
+        self.chunk_id = chunk_id;
+        self.tile_index_in_chunk = tile_index_in_chunk;
+        self.failed = false;
+    }
+}
+
+impl Iterator for ArchiveCursor {
+    type Item = Result<DecodedTilePlacement, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        loop {
+            if self.chunk_id as usize >= self.archive.meta().chunk_descs.len() {
+                return None;
+            }
+
+            let chunk = match &self.chunk {
+                Some(chunk) => chunk,
+                None => match self.archive.read_chunk(self.chunk_id) {
+                    Ok(chunk) => self.chunk.insert(chunk),
+                    Err(err) => {
+                        self.failed = true;
+                        return Some(Err(err));
+                    }
+                },
+            };
+
+            if let Some(tile) = chunk.get(self.tile_index_in_chunk) {
+                self.tile_index_in_chunk += 1;
+                return Some(Ok(tile.clone()));
+            }
+
+            self.move_to(self.chunk_id + 1, 0);
+        }
+    }
+}
+
+/// Decoded chunks, evicting the least recently used one once full.
+struct ChunkCache {
+    capacity: usize,
+    // Ordered from least to most recently used
+    chunks: VecDeque<(u32, Arc<Vec<DecodedTilePlacement>>)>,
+}
+
+impl ChunkCache {
+    fn new(capacity: usize) -> Self {
+        ChunkCache {
+            capacity,
+            chunks: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn get(&mut self, chunk_id: u32) -> Option<Arc<Vec<DecodedTilePlacement>>> {
+        let position = self.chunks.iter().position(|(id, _)| *id == chunk_id)?;
+        let entry = self.chunks.remove(position)?;
+        let chunk = entry.1.clone();
+        self.chunks.push_back(entry);
+
+        Some(chunk)
+    }
+
+    fn insert(&mut self, chunk_id: u32, chunk: Arc<Vec<DecodedTilePlacement>>) {
+        // Another cursor may have read the same chunk in the meantime
+        if self.capacity == 0 || self.get(chunk_id).is_some() {
+            return;
+        }
+
+        if self.chunks.len() == self.capacity {
+            self.chunks.pop_front();
+        }
+        self.chunks.push_back((chunk_id, chunk));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        io::{Read, Seek, SeekFrom},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use chrono::NaiveDateTime;
+    use tempfile::NamedTempFile;
+
+    use crate::{
+        errors::ReadError,
+        structures::{ChunkingStrategy, Compression},
+        PlacedArchiveReader, PlacedArchiveWriter, PlacedArchiveWriterOptions,
+    };
+
+    use super::{ChunkCache, PlacedArchive, PlacedArchiveOptions};
+
+    fn write_archive() -> NamedTempFile {
+        let file = NamedTempFile::new().unwrap();
+        let mut archive_writer = PlacedArchiveWriter::with_options(
+            file.reopen().unwrap(),
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(100),
+                compression: Compression::Brotli(1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for i in 0..1000u32 {
+            archive_writer
+                .add_tile(
+                    (i % 40) as u16,
+                    (i / 40) as u16,
+                    [(i % 3) as u8, 0, 0, 255],
+                    NaiveDateTime::from_timestamp_millis(i as i64 * 10).unwrap(),
+                )
+                .unwrap();
+        }
+        archive_writer.finalize().unwrap();
+
+        file
+    }
+
+    #[test]
+    fn independent_cursors() {
+        let file = write_archive();
+        let archive = PlacedArchive::with_options(
+            file.reopen().unwrap(),
+            PlacedArchiveOptions {
+                chunk_cache_capacity: 2,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let reader = PlacedArchiveReader::new(file.reopen().unwrap()).unwrap();
+        assert_eq!(archive.meta(), &reader.meta);
+        let expected_tiles = reader.collect::<Vec<_>>();
+
+        let mut early_cursor = archive.cursor();
+        let mut late_cursor = archive.cursor();
+        assert_eq!(late_cursor.seek_to_ms(7005).unwrap(), 701);
+        assert_eq!(early_cursor.next().unwrap().unwrap(), expected_tiles[0]);
+        assert_eq!(late_cursor.next().unwrap().unwrap(), expected_tiles[701]);
+        early_cursor.seek_to_tile_index(250);
+        assert_eq!(early_cursor.next().unwrap().unwrap(), expected_tiles[250]);
+
+        // Cursors can be moved to other threads, sharing the cache
+        let threads = [0, 333, 999]
+            .map(|start| {
+                let mut cursor = archive.cursor();
+                std::thread::spawn(move || {
+                    cursor.seek_to_tile_index(start);
+                    cursor.map(Result::unwrap).collect::<Vec<_>>()
+                })
+            })
+            .map(|thread| thread.join().unwrap());
+        assert_eq!(threads[0], expected_tiles);
+        assert_eq!(threads[1], expected_tiles[333..]);
+        assert_eq!(threads[2], expected_tiles[999..]);
+
+        let mut cursor = archive.cursor();
+        cursor.seek_to_tile_index(1000);
+        assert!(cursor.next().is_none());
+        assert_eq!(cursor.seek_to_ms(u64::MAX).unwrap(), 1000);
+        assert!(cursor.next().is_none());
+    }
+
+    #[test]
+    fn chunk_cache_evicts_least_recently_used() {
+        let mut cache = ChunkCache::new(2);
+        cache.insert(0, Arc::new(Vec::new()));
+        cache.insert(1, Arc::new(Vec::new()));
+        assert!(cache.get(0).is_some());
+
+        cache.insert(2, Arc::new(Vec::new()));
+        assert!(cache.get(1).is_none());
+        assert!(cache.get(0).is_some());
+        assert!(cache.get(2).is_some());
+    }
+
+    /// File that panics when read once `panic_on_read` is set.
+    struct PanickingFile {
+        file: File,
+        panic_on_read: Arc<AtomicBool>,
+    }
+
+    impl Read for PanickingFile {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            assert!(!self.panic_on_read.load(Ordering::Relaxed));
+            self.file.read(buf)
+        }
+    }
+
+    impl Seek for PanickingFile {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.file.seek(pos)
+        }
+    }
+
+    #[test]
+    fn reader_thread_panic_is_an_error() {
+        let file = write_archive();
+        let panic_on_read = Arc::new(AtomicBool::new(false));
+        let archive = PlacedArchive::new(PanickingFile {
+            file: file.reopen().unwrap(),
+            panic_on_read: panic_on_read.clone(),
+        })
+        .unwrap();
+
+        panic_on_read.store(true, Ordering::Relaxed);
+        assert!(matches!(
+            archive.read_chunk(3),
+            Err(ReadError::ReaderThreadExited(3))
+        ));
+        // Requests fail instead of waiting once the reader thread has exited
+        assert!(matches!(
+            archive.cursor().next(),
+            Some(Err(ReadError::ReaderThreadExited(0)))
+        ));
+    }
+}
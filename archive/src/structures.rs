@@ -1,18 +1,26 @@
 use bincode::{Decode, Encode};
-use std::{collections::BTreeMap, io::Write};
+use chrono::NaiveDateTime;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+    num::NonZeroU32,
+};
 
-use crate::constants::BINCODE_CONFIG;
+use crate::constants::{BINCODE_CONFIG, COMPACT_BINCODE_CONFIG};
 
 #[derive(Encode, Decode, PartialEq, Eq, Debug)]
 #[repr(C)]
 pub struct StoredTilePlacement {
     pub x: u16,
     pub y: u16,
-    pub color_index: u8,
-    pub ms_since_epoch: u32,
+    pub color_index: u16,
+    pub ms_since_epoch: u64,
 }
 
 impl StoredTilePlacement {
+    /// Never assigned to a color, so it can mark placements that should be ignored (e.g. padding on the GPU).
+    pub const PADDING_COLOR_INDEX: u16 = u16::MAX;
+
     pub fn encoded_size() -> usize {
         let mut buf = Vec::new();
         bincode::encode_into_std_write(
@@ -35,40 +43,367 @@ impl StoredTilePlacement {
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct DecodedTilePlacement {
     pub x: u16,
     pub y: u16,
-    pub ms_since_epoch: u32,
+    pub ms_since_epoch: u64,
     /// rgba
     pub color: [u8; 4],
+    /// Index into the archive's user table, if it records who placed the tile.
+    pub user_id: Option<u32>,
+}
+
+impl DecodedTilePlacement {
+    /// Returns when the tile was placed, if the archive records when its first tile was placed.
+    pub fn placed_at(&self, meta: &Meta) -> Option<NaiveDateTime> {
+        meta.first_tile_placed_at()?
+            .checked_add_signed(chrono::Duration::milliseconds(self.ms_since_epoch as i64))
+    }
 }
 
 #[derive(Encode, Decode, PartialEq, Eq, Debug, Clone)]
 pub struct CanvasSizeChange {
     pub width: u16,
     pub height: u16,
-    pub ms_since_epoch: u32,
+    pub ms_since_epoch: u64,
 }
 
 #[derive(Encode, Decode, PartialEq, Eq, Debug, Clone)]
 pub struct ChunkDescription {
     pub id: u32,
-    pub up_to_ms_since_epoch: u32,
+    pub up_to_ms_since_epoch: u64,
     pub num_tiles: u32,
+    /// CRC-32 of the chunk's tile file, as stored in the archive. Not recorded by version 0 archives.
+    pub checksum: Option<u32>,
+}
+
+/// Rectangle on the canvas, including its maximum coordinates.
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct BoundingBox {
+    pub min_x: u16,
+    pub min_y: u16,
+    pub max_x: u16,
+    pub max_y: u16,
+}
+
+impl BoundingBox {
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        (self.min_x..=self.max_x).contains(&x) && (self.min_y..=self.max_y).contains(&y)
+    }
+
+    pub fn intersection(&self, other: &BoundingBox) -> Option<BoundingBox> {
+        let intersection = BoundingBox {
+            min_x: self.min_x.max(other.min_x),
+            min_y: self.min_y.max(other.min_y),
+            max_x: self.max_x.min(other.max_x),
+            max_y: self.max_y.min(other.max_y),
+        };
+
+        if intersection.min_x <= intersection.max_x && intersection.min_y <= intersection.max_y {
+            Some(intersection)
+        } else {
+            None
+        }
+    }
+
+    fn width(&self) -> u64 {
+        (self.max_x - self.min_x) as u64 + 1
+    }
+}
+
+/// Pixels touched by the tiles of a chunk, stored as `footprints/{chunk_id}` next to the chunk.
+/// Not recorded by version 0 archives.
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone)]
+pub struct ChunkFootprint {
+    pub bounding_box: BoundingBox,
+    /// Pixels within `bounding_box` that were placed at least once
+    touched_pixels: FootprintBitmap,
+}
+
+/// Bitmap of pixels within a bounding box, row by row, in whichever encoding is smaller.
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone)]
+enum FootprintBitmap {
+    /// One bit per pixel, least significant bit first
+    Raw(Vec<u8>),
+    /// Variable-length lengths of alternating untouched and touched runs, starting with an untouched one
+    Runs(Vec<u8>),
+}
+
+impl ChunkFootprint {
+    /// Builds the footprint of a chunk from its touched pixels, given as `(y, x)` so they're ordered row by row.
+    pub(crate) fn from_touched_pixels(pixels: &BTreeSet<(u16, u16)>) -> Option<Self> {
+        let bounding_box = BoundingBox {
+            min_x: pixels.iter().map(|(_, x)| *x).min()?,
+            min_y: pixels.first()?.0,
+            max_x: pixels.iter().map(|(_, x)| *x).max()?,
+            max_y: pixels.last()?.0,
+        };
+
+        let mut runs: Vec<(u64, u64)> = Vec::new();
+        for (y, x) in pixels {
+            let position = (y - bounding_box.min_y) as u64 * bounding_box.width()
+                + (x - bounding_box.min_x) as u64;
+
+            match runs.last_mut() {
+                Some((start, length)) if *start + *length == position => *length += 1,
+                _ => runs.push((position, 1)),
+            }
+        }
+
+        let mut encoded_runs = Vec::new();
+        let mut next_position = 0;
+        for (start, length) in &runs {
+            bincode::encode_into_std_write(
+                start - next_position,
+                &mut encoded_runs,
+                COMPACT_BINCODE_CONFIG,
+            )
+            .unwrap();
+            bincode::encode_into_std_write(length, &mut encoded_runs, COMPACT_BINCODE_CONFIG)
+                .unwrap();
+
+            next_position = start + length;
+        }
+
+        // Scattered pixels take up a few bytes each as runs, but only a bit each in a raw bitmap
+        let area = bounding_box.width() * ((bounding_box.max_y - bounding_box.min_y) as u64 + 1);
+        let touched_pixels = match area.div_ceil(8) < encoded_runs.len() as u64 {
+            true => {
+                let mut bits = vec![0; area.div_ceil(8) as usize];
+                for position in runs
+                    .iter()
+                    .flat_map(|(start, length)| *start..*start + *length)
+                {
+                    bits[(position / 8) as usize] |= 1 << (position % 8);
+                }
+                FootprintBitmap::Raw(bits)
+            }
+            false => FootprintBitmap::Runs(encoded_runs),
+        };
+
+        Some(ChunkFootprint {
+            bounding_box,
+            touched_pixels,
+        })
+    }
+
+    pub fn touches(&self, x: u16, y: u16) -> bool {
+        self.touches_region(&BoundingBox {
+            min_x: x,
+            min_y: y,
+            max_x: x,
+            max_y: y,
+        })
+    }
+
+    /// Returns true if any pixel within `region` was touched.
+    pub fn touches_region(&self, region: &BoundingBox) -> bool {
+        let region = match self.bounding_box.intersection(region) {
+            Some(region) => region,
+            None => return false,
+        };
+
+        let width = self.bounding_box.width();
+        let first_row = (region.min_y - self.bounding_box.min_y) as u64;
+        let last_row = (region.max_y - self.bounding_box.min_y) as u64;
+        let first_column = (region.min_x - self.bounding_box.min_x) as u64;
+        let last_column = (region.max_x - self.bounding_box.min_x) as u64;
+
+        self.touched_runs().any(|(start, end)| {
+            // A run covering a whole row touches every column in it
+            (first_row.max(start / width)..=last_row.min((end - 1) / width)).any(|row| {
+                let run_first_column = match row == start / width {
+                    true => start % width,
+                    false => 0,
+                };
+                let run_last_column = match row == (end - 1) / width {
+                    true => (end - 1) % width,
+                    false => width - 1,
+                };
+
+                run_first_column <= last_column && first_column <= run_last_column
+            })
+        })
+    }
+
+    /// Returns the touched pixels, row by row.
+    pub(crate) fn pixels(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        let width = self.bounding_box.width();
+
+        self.touched_runs()
+            .flat_map(|(start, end)| start..end)
+            .map(move |position| {
+                (
+                    self.bounding_box.min_x + (position % width) as u16,
+                    self.bounding_box.min_y + (position / width) as u16,
+                )
+            })
+    }
+
+    /// Returns the runs of touched pixels as ranges of positions within the bounding box.
+    fn touched_runs(&self) -> Box<dyn Iterator<Item = (u64, u64)> + '_> {
+        let encoded_runs = match &self.touched_pixels {
+            FootprintBitmap::Raw(bits) => {
+                let num_bits = bits.len() as u64 * 8;
+                let is_touched =
+                    |position: u64| bits[(position / 8) as usize] & (1 << (position % 8)) != 0;
+                let mut position = 0;
+
+                return Box::new(std::iter::from_fn(move || {
+                    while position < num_bits && !is_touched(position) {
+                        position += 1;
+                    }
+                    let start = position;
+                    while position < num_bits && is_touched(position) {
+                        position += 1;
+                    }
+
+                    (start < position).then_some((start, position))
+                }));
+            }
+            FootprintBitmap::Runs(encoded_runs) => encoded_runs,
+        };
+
+        let mut remaining = encoded_runs.as_slice();
+        let mut next_position = 0;
+
+        Box::new(std::iter::from_fn(move || {
+            let (gap, gap_size): (u64, usize) =
+                bincode::decode_from_slice(remaining, COMPACT_BINCODE_CONFIG).ok()?;
+            let (length, length_size): (u64, usize) =
+                bincode::decode_from_slice(&remaining[gap_size..], COMPACT_BINCODE_CONFIG).ok()?;
+            remaining = &remaining[gap_size + length_size..];
+
+            let start = next_position + gap;
+            next_position = start + length;
+
+            Some((start, next_position))
+        }))
+    }
+}
+
+/// Area of the canvas filled by a moderator edit.
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum EditShape {
+    Rectangle(BoundingBox),
+    /// Every pixel whose center is at most `radius` pixels away from (`x`, `y`).
+    Circle {
+        x: u16,
+        y: u16,
+        radius: u16,
+    },
+}
+
+impl EditShape {
+    /// Returns the pixels within the shape, row by row.
+    pub fn pixels(&self) -> Box<dyn Iterator<Item = (u16, u16)>> {
+        match *self {
+            EditShape::Rectangle(bounding_box) => Box::new(
+                (bounding_box.min_y..=bounding_box.max_y).flat_map(move |y| {
+                    (bounding_box.min_x..=bounding_box.max_x).map(move |x| (x, y))
+                }),
+            ),
+            EditShape::Circle { x, y, radius } => {
+                let squared_radius = radius as i64 * radius as i64;
+                Box::new(
+                    (y.saturating_sub(radius)..=y.saturating_add(radius)).flat_map(
+                        move |pixel_y| {
+                            (x.saturating_sub(radius)..=x.saturating_add(radius))
+                                .filter(move |pixel_x| {
+                                    let dx = *pixel_x as i64 - x as i64;
+                                    let dy = pixel_y as i64 - y as i64;
+                                    dx * dx + dy * dy <= squared_radius
+                                })
+                                .map(move |pixel_x| (pixel_x, pixel_y))
+                        },
+                    ),
+                )
+            }
+        }
+    }
+}
+
+/// A moderator filling an area of the canvas with one color at once.
+/// The filled pixels are also stored as regular tile placements, so readers that don't know about edits still apply them.
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone)]
+pub struct ModeratorEdit {
+    pub ms_since_epoch: u64,
+    pub shape: EditShape,
+    pub color_index: u16,
+}
+
+/// How tile placements are grouped into chunks when an archive is written.
+/// Frame-grid chunks are only cut between frames, see `TileEncoding::FrameGrid`.
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ChunkingStrategy {
+    /// Each chunk contains at most this many tiles.
+    TileCount(u32),
+    /// Each chunk's encoded tile data is at most this many bytes (a chunk always contains at least one tile).
+    MaxBytes(u64),
+    /// Each chunk spans at most this many milliseconds, measured from its first tile.
+    TimeWindow(u32),
+}
+
+impl Default for ChunkingStrategy {
+    fn default() -> Self {
+        ChunkingStrategy::TileCount(1_000_000)
+    }
+}
+
+/// Compression applied to the files inside an archive.
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Brotli compression (using MLA's compression layer) with the given level, from 0 to 11.
+    Brotli(u32),
+}
+
+/// How tile placements are encoded within a chunk.
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum TileEncoding {
+    /// Every tile is a fixed-width `StoredTilePlacement`.
+    #[default]
+    Fixed,
+    /// Coordinates and color indices are variable-length integers, and timestamps are deltas from the previous tile in the chunk.
+    /// Each chunk has an index of the offset of every `COMPACT_TILE_INDEX_INTERVAL`th tile (`tile_offsets/{chunk_id}`), so it can
+    /// be decoded a block of tiles at a time when seeking within it.
+    Compact,
+    /// Chunks are split into frames spanning `frame_ms` milliseconds, each storing the run-length encoded cells that changed during it.
+    /// Only the last placement of a cell within a frame is kept, and it's reported as placed at the start of its frame.
+    /// Chunks are only cut between frames, so the chunking strategy's limits apply to placements before they're deduplicated,
+    /// and a chunk may exceed them to complete its last frame.
+    FrameGrid { frame_ms: NonZeroU32 },
 }
 
 #[derive(Encode, Decode, PartialEq, Eq, Debug, Clone)]
 pub struct Meta {
+    /// Absolute time the first tile was placed, which all other `ms_since_epoch` values are relative to.
+    /// Not recorded by version 0 archives.
+    pub first_tile_placed_at_ms_since_unix_epoch: Option<i64>,
     pub canvas_size_changes: Vec<CanvasSizeChange>,
+    pub chunking_strategy: ChunkingStrategy,
+    pub compression: Compression,
+    pub tile_encoding: TileEncoding,
+    /// Number of tiles stored in the chunks, which for frame-grid archives excludes placements replaced within the same frame.
     pub total_tile_placements: u64,
-    pub last_tile_placed_at_ms_since_epoch: u32,
+    pub last_tile_placed_at_ms_since_epoch: u64,
     /// rgba
-    pub color_id_to_tuple: BTreeMap<u8, [u8; 4]>,
+    pub color_id_to_tuple: BTreeMap<u16, [u8; 4]>,
     pub chunk_descs: Vec<ChunkDescription>,
+    /// Number of entries in the user table, or `None` if the archive doesn't record who placed tiles.
+    /// Not recorded by version 0 archives.
+    pub num_users: Option<u32>,
+    /// Not recorded by version 0 archives.
+    pub moderator_edits: Vec<ModeratorEdit>,
 }
 
 impl Meta {
+    pub fn first_tile_placed_at(&self) -> Option<NaiveDateTime> {
+        NaiveDateTime::from_timestamp_millis(self.first_tile_placed_at_ms_since_unix_epoch?)
+    }
+
     pub fn get_largest_canvas_size(&self) -> Option<CanvasSizeChange> {
         Some(
             self.canvas_size_changes
@@ -77,4 +412,15 @@ impl Meta {
                 .clone(),
         )
     }
+
+    /// Returns the canvas size in effect at `ms_since_epoch`.
+    pub fn get_canvas_size_at(&self, ms_since_epoch: u64) -> Option<CanvasSizeChange> {
+        let num_of_changes_so_far = self
+            .canvas_size_changes
+            .partition_point(|x| x.ms_since_epoch <= ms_since_epoch);
+
+        self.canvas_size_changes
+            .get(num_of_changes_so_far.saturating_sub(1))
+            .cloned()
+    }
 }
@@ -0,0 +1,280 @@
+use std::{collections::BTreeMap, io::Cursor, num::NonZeroU32};
+
+use bincode::{
+    error::{DecodeError, EncodeError},
+    Decode, Encode,
+};
+
+use crate::{
+    constants::{BINCODE_CONFIG, COMPACT_BINCODE_CONFIG, COMPACT_TILE_INDEX_INTERVAL},
+    structures::{StoredTilePlacement, TileEncoding},
+};
+
+/// On-disk representation of a tile in a `TileEncoding::Compact` chunk.
+#[derive(Encode, Decode)]
+struct CompactTilePlacement {
+    x: u16,
+    y: u16,
+    color_index: u16,
+    /// For the first tile in a chunk, this is relative to the start of the archive
+    ms_since_previous_tile: u64,
+}
+
+/// Position in a `TileEncoding::Compact` chunk that decoding can start from.
+#[derive(Encode, Decode, Clone, Copy, Default)]
+pub(crate) struct CompactTileOffset {
+    pub byte_offset: u64,
+    /// Timestamp of the preceding tile (or 0 for the first tile), which the tile at `byte_offset` is relative to
+    pub previous_ms_since_epoch: u64,
+}
+
+/// Offset index of a `TileEncoding::Compact` chunk, with an entry for every `COMPACT_TILE_INDEX_INTERVAL`th tile.
+/// Stored as `tile_offsets/{chunk_id}` next to the chunk, so tiles can be reached without decoding the whole chunk.
+#[derive(Encode, Decode, Default)]
+pub(crate) struct CompactTileIndex {
+    pub num_tiles: u32,
+    pub offsets: Vec<CompactTileOffset>,
+}
+
+impl CompactTileIndex {
+    /// Records a tile that is about to be appended to the chunk at `byte_offset`.
+    pub fn push(&mut self, byte_offset: usize, previous_ms_since_epoch: u64) {
+        if (self.num_tiles as usize).is_multiple_of(COMPACT_TILE_INDEX_INTERVAL) {
+            self.offsets.push(CompactTileOffset {
+                byte_offset: byte_offset as u64,
+                previous_ms_since_epoch,
+            });
+        }
+        self.num_tiles += 1;
+    }
+
+    /// Builds the index of a chunk that was written without one.
+    pub fn build(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut compact_tiles = Cursor::new(buf);
+        let mut index = Self::default();
+        let mut ms_since_epoch = 0;
+
+        while compact_tiles.position() < buf.len() as u64 {
+            index.push(compact_tiles.position() as usize, ms_since_epoch);
+            let tile: CompactTilePlacement =
+                bincode::decode_from_std_read(&mut compact_tiles, COMPACT_BINCODE_CONFIG)?;
+            ms_since_epoch += tile.ms_since_previous_tile;
+        }
+
+        Ok(index)
+    }
+}
+
+/// Header of a frame in a `TileEncoding::FrameGrid` chunk, followed by `num_of_runs` runs.
+#[derive(Encode, Decode)]
+struct FrameHeader {
+    /// For the first frame in a chunk, this is the index of the frame since the start of the archive
+    frames_since_previous_frame: u64,
+    num_of_runs: u32,
+}
+
+/// Header of a run of consecutive changed cells, followed by `length` color indices.
+/// Cells are numbered row by row, with every row spanning `FRAME_GRID_ROW_LENGTH` cells regardless of the canvas size.
+#[derive(Encode, Decode)]
+struct RunHeader {
+    /// Number of unchanged cells between the end of the previous run (or the first cell) and this run
+    cells_since_previous_run: u32,
+    length: u32,
+}
+
+const FRAME_GRID_ROW_LENGTH: u32 = u16::MAX as u32 + 1;
+
+impl TileEncoding {
+    /// Upper bound for the number of bytes a single tile takes up in a chunk.
+    pub fn max_encoded_size(&self) -> usize {
+        match self {
+            TileEncoding::Fixed => StoredTilePlacement::encoded_size(),
+            // Varints take up to 3 bytes for a u16 and 9 bytes for a u64
+            TileEncoding::Compact => 3 * 3 + 9,
+            // Tiles are buffered in the fixed-width layout and only converted to frames once the chunk is complete
+            TileEncoding::FrameGrid { .. } => StoredTilePlacement::encoded_size(),
+        }
+    }
+}
+
+/// Appends `tile` to a chunk. `previous_ms_since_epoch` is the timestamp of the previous tile in the chunk (or 0 for the first tile).
+pub(crate) fn encode_tile(
+    tile_encoding: TileEncoding,
+    tile: StoredTilePlacement,
+    previous_ms_since_epoch: u64,
+    buf: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    match tile_encoding {
+        TileEncoding::Fixed | TileEncoding::FrameGrid { .. } => {
+            bincode::encode_into_std_write(tile, buf, BINCODE_CONFIG)?
+        }
+        TileEncoding::Compact => bincode::encode_into_std_write(
+            CompactTilePlacement {
+                x: tile.x,
+                y: tile.y,
+                color_index: tile.color_index,
+                ms_since_previous_tile: tile.ms_since_epoch - previous_ms_since_epoch,
+            },
+            buf,
+            COMPACT_BINCODE_CONFIG,
+        )?,
+    };
+
+    Ok(())
+}
+
+/// Converts a `TileEncoding::Compact` chunk into consecutive fixed-width `StoredTilePlacement`s.
+pub(crate) fn decode_compact_tiles(buf: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let mut decoded_buf = Vec::new();
+    decode_compact_tiles_from(
+        buf,
+        CompactTileOffset::default(),
+        usize::MAX,
+        &mut decoded_buf,
+    )?;
+
+    Ok(decoded_buf)
+}
+
+/// Decodes up to `max_tiles` tiles of a `TileEncoding::Compact` chunk, starting at `offset`,
+/// and appends them to `decoded_buf` as fixed-width `StoredTilePlacement`s.
+pub(crate) fn decode_compact_tiles_from(
+    buf: &[u8],
+    offset: CompactTileOffset,
+    max_tiles: usize,
+    decoded_buf: &mut Vec<u8>,
+) -> Result<(), DecodeError> {
+    let mut compact_tiles = Cursor::new(buf);
+    compact_tiles.set_position(offset.byte_offset);
+    let mut ms_since_epoch = offset.previous_ms_since_epoch;
+
+    for _ in 0..max_tiles {
+        if compact_tiles.position() >= buf.len() as u64 {
+            break;
+        }
+
+        let tile: CompactTilePlacement =
+            bincode::decode_from_std_read(&mut compact_tiles, COMPACT_BINCODE_CONFIG)?;
+        ms_since_epoch += tile.ms_since_previous_tile;
+
+        StoredTilePlacement {
+            x: tile.x,
+            y: tile.y,
+            color_index: tile.color_index,
+            ms_since_epoch,
+        }
+        .write_into(decoded_buf);
+    }
+
+    Ok(())
+}
+
+/// Converts a chunk of fixed-width tiles into a `TileEncoding::FrameGrid` chunk.
+/// Returns the encoded chunk, the number of tiles it decodes to and the timestamp of its last frame.
+pub(crate) fn encode_frame_grid(
+    fixed_tiles: &[u8],
+    frame_ms: NonZeroU32,
+) -> Result<(Vec<u8>, u32, u64), EncodeError> {
+    let mut buf = Vec::new();
+    let mut num_tiles = 0;
+    let mut previous_frame_index = 0;
+
+    let mut frames: Vec<(u64, BTreeMap<u32, u16>)> = Vec::new();
+    for encoded_tile in fixed_tiles.chunks_exact(StoredTilePlacement::encoded_size()) {
+        let (tile, _): (StoredTilePlacement, usize) =
+            bincode::decode_from_slice(encoded_tile, BINCODE_CONFIG)
+                .map_err(|_| EncodeError::Other("could not decode buffered tile"))?;
+        let frame_index = tile.ms_since_epoch / frame_ms.get() as u64;
+
+        if frames.last().map(|(index, _)| *index) != Some(frame_index) {
+            frames.push((frame_index, BTreeMap::new()));
+        }
+
+        // Later placements of the same cell replace earlier ones
+        let cell = tile.y as u32 * FRAME_GRID_ROW_LENGTH + tile.x as u32;
+        frames.last_mut().unwrap().1.insert(cell, tile.color_index);
+    }
+
+    for (frame_index, changed_cells) in &frames {
+        let mut runs: Vec<(u32, Vec<u16>)> = Vec::new();
+        for (cell, color_index) in changed_cells {
+            match runs.last_mut() {
+                Some((start, colors)) if *start + colors.len() as u32 == *cell => {
+                    colors.push(*color_index)
+                }
+                _ => runs.push((*cell, vec![*color_index])),
+            }
+        }
+
+        bincode::encode_into_std_write(
+            FrameHeader {
+                frames_since_previous_frame: frame_index - previous_frame_index,
+                num_of_runs: runs.len() as u32,
+            },
+            &mut buf,
+            COMPACT_BINCODE_CONFIG,
+        )?;
+
+        let mut next_cell = 0;
+        for (start, colors) in runs {
+            bincode::encode_into_std_write(
+                RunHeader {
+                    cells_since_previous_run: start - next_cell,
+                    length: colors.len() as u32,
+                },
+                &mut buf,
+                COMPACT_BINCODE_CONFIG,
+            )?;
+            for color_index in &colors {
+                bincode::encode_into_std_write(color_index, &mut buf, COMPACT_BINCODE_CONFIG)?;
+            }
+
+            next_cell = start + colors.len() as u32;
+        }
+
+        num_tiles += changed_cells.len() as u32;
+        previous_frame_index = *frame_index;
+    }
+
+    Ok((buf, num_tiles, previous_frame_index * frame_ms.get() as u64))
+}
+
+/// Converts a `TileEncoding::FrameGrid` chunk into consecutive fixed-width `StoredTilePlacement`s.
+pub(crate) fn decode_frame_grid_tiles(
+    buf: &[u8],
+    frame_ms: NonZeroU32,
+) -> Result<Vec<u8>, DecodeError> {
+    let mut frames = Cursor::new(buf);
+    let mut decoded_buf = Vec::new();
+    let mut frame_index = 0;
+
+    while frames.position() < buf.len() as u64 {
+        let frame: FrameHeader =
+            bincode::decode_from_std_read(&mut frames, COMPACT_BINCODE_CONFIG)?;
+        frame_index += frame.frames_since_previous_frame;
+
+        let mut next_cell = 0;
+        for _ in 0..frame.num_of_runs {
+            let run: RunHeader =
+                bincode::decode_from_std_read(&mut frames, COMPACT_BINCODE_CONFIG)?;
+            next_cell += run.cells_since_previous_run;
+
+            for _ in 0..run.length {
+                let color_index: u16 =
+                    bincode::decode_from_std_read(&mut frames, COMPACT_BINCODE_CONFIG)?;
+
+                StoredTilePlacement {
+                    x: (next_cell % FRAME_GRID_ROW_LENGTH) as u16,
+                    y: (next_cell / FRAME_GRID_ROW_LENGTH) as u16,
+                    color_index,
+                    ms_since_epoch: frame_index * frame_ms.get() as u64,
+                }
+                .write_into(&mut decoded_buf);
+
+                next_cell += 1;
+            }
+        }
+    }
+
+    Ok(decoded_buf)
+}
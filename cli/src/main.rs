@@ -1,8 +1,11 @@
-use archive::{PlacedArchiveReader, PlacedArchiveWriter};
+use archive::{
+    structures::{BoundingBox, ChunkingStrategy, Compression, EditShape, TileEncoding},
+    PlacedArchiveReader, PlacedArchiveWriter, PlacedArchiveWriterOptions, PublicKey, StaticSecret,
+};
 use chrono::NaiveDateTime;
 use clap::{Parser, Subcommand};
 use colors_transform::Color;
-use std::fs::File;
+use std::{fs::File, num::NonZeroU32};
 
 // todo: use https://github.com/emersonford/tracing-indicatif for automatic progress bars?
 
@@ -17,7 +20,47 @@ struct Cli {
 #[derive(Debug, Subcommand)]
 enum Commands {
     /// Repack data from a CSV into an archive containing color and tile data
-    Pack { in_file: String, out_file: String },
+    Pack {
+        in_file: String,
+        out_file: String,
+        #[clap(long, group = "chunking")]
+        /// maximum number of tiles per chunk
+        chunk_tiles: Option<u32>,
+        #[clap(long, group = "chunking")]
+        /// maximum size of a chunk's tile data in bytes
+        chunk_bytes: Option<u64>,
+        #[clap(long, group = "chunking")]
+        /// maximum time span of a chunk in milliseconds
+        chunk_ms: Option<u32>,
+        #[clap(long)]
+        /// maximum number of placements held in memory before they're spilled to disk
+        max_tiles_in_memory: Option<usize>,
+        #[clap(long)]
+        /// compress the archive with the given Brotli level (0-11)
+        compression_level: Option<u32>,
+        #[clap(long)]
+        /// the CSV is already sorted by timestamp, so placements can be streamed straight into the archive
+        presorted: bool,
+        #[clap(long, group = "tile_encoding")]
+        /// store placements with variable-length integers and delta-encoded timestamps
+        compact: bool,
+        #[clap(long, group = "tile_encoding")]
+        /// store the cells changed during each frame of this many milliseconds, keeping only the last placement per cell
+        frame_ms: Option<NonZeroU32>,
+        #[clap(long)]
+        /// record the hashed user id of each placement
+        users: bool,
+        #[clap(long)]
+        /// continue this archive with the placements in the CSV, which must not be placed before its last one.
+        /// Only archives written in the current format can be continued, older ones have to be packed again from their CSV
+        append_to: Option<String>,
+        #[clap(long, requires = "append_to")]
+        /// private key file to decrypt the archive given with --append-to
+        private_key: Option<String>,
+        #[clap(long)]
+        /// encrypt the archive for the owner of this public key file, can be given multiple times
+        public_key: Vec<String>,
+    },
     /// Render history to an image
     Render {
         archive_path: String,
@@ -25,24 +68,119 @@ enum Commands {
         #[clap(short, long, default_value = "0")]
         /// if 0, render all history
         up_to_seconds: u32,
+        #[clap(long)]
+        /// private key file to decrypt the archive with
+        private_key: Option<String>,
     },
     Play {
         archive_path: String,
         #[clap(short, long, default_value = "1")]
         timescale_factor: f32,
+        #[clap(short, long, default_value = "0")]
+        /// start playback from this many seconds into the archive
+        start_at_seconds: u32,
+        #[clap(long)]
+        /// private key file to decrypt the archive with
+        private_key: Option<String>,
+    },
+    /// Print every tile placed at a pixel, or within a rectangle starting at it
+    History {
+        archive_path: String,
+        x: u16,
+        y: u16,
+        #[clap(long, default_value = "1")]
+        width: u16,
+        #[clap(long, default_value = "1")]
+        height: u16,
+        #[clap(long)]
+        /// print the placements as a JSON array instead of a table
+        json: bool,
+        #[clap(long)]
+        /// private key file to decrypt the archive with
+        private_key: Option<String>,
+    },
+    /// Check an archive for missing or corrupted chunks
+    Verify {
+        archive_path: String,
+        #[clap(long)]
+        /// private key file to decrypt the archive with
+        private_key: Option<String>,
     },
+    /// Generate a key pair for encrypting archives, written to the given file and a ".pub" file next to it
+    Keygen { out_file: String },
 }
 
 fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Pack { in_file, out_file } => {
+        Commands::Pack {
+            in_file,
+            out_file,
+            chunk_tiles,
+            chunk_bytes,
+            chunk_ms,
+            max_tiles_in_memory,
+            compression_level,
+            presorted,
+            compact,
+            frame_ms,
+            users,
+            append_to,
+            private_key,
+            public_key,
+        } => {
             let file = File::open(in_file).expect("Could not open file");
             let mut reader = csv::Reader::from_reader(file);
 
+            let chunking_strategy = match (chunk_tiles, chunk_bytes, chunk_ms) {
+                (Some(max_tiles), _, _) => ChunkingStrategy::TileCount(max_tiles),
+                (_, Some(max_bytes), _) => ChunkingStrategy::MaxBytes(max_bytes),
+                (_, _, Some(window_ms)) => ChunkingStrategy::TimeWindow(window_ms),
+                _ => ChunkingStrategy::default(),
+            };
+
+            let mut options = PlacedArchiveWriterOptions {
+                chunking_strategy,
+                compression: match compression_level {
+                    Some(level) => Compression::Brotli(level),
+                    None => Compression::None,
+                },
+                presorted_input: presorted,
+                tile_encoding: match (compact, frame_ms) {
+                    (true, _) => TileEncoding::Compact,
+                    (_, Some(frame_ms)) => TileEncoding::FrameGrid { frame_ms },
+                    _ => TileEncoding::Fixed,
+                },
+                generate_snapshots: true,
+                encryption_public_keys: public_key
+                    .iter()
+                    .map(|path| PublicKey::from(read_key_file(path)))
+                    .collect(),
+                ..Default::default()
+            };
+            if let Some(max_tiles_in_memory) = max_tiles_in_memory {
+                options.max_tiles_in_memory = max_tiles_in_memory;
+            }
+
             let out_file = File::create(out_file).expect("Could not create file");
-            let mut archive_writer = PlacedArchiveWriter::new(out_file);
+            let mut archive_writer = match append_to {
+                Some(existing_archive_path) => {
+                    let existing_file =
+                        File::open(existing_archive_path).expect("Could not open archive");
+                    match private_key {
+                        Some(path) => PlacedArchiveWriter::append_with_private_keys(
+                            existing_file,
+                            &[StaticSecret::from(read_key_file(&path))],
+                            out_file,
+                            options,
+                        ),
+                        None => PlacedArchiveWriter::append(existing_file, out_file, options),
+                    }
+                }
+                None => PlacedArchiveWriter::with_options(out_file, options),
+            }
+            .expect("Could not create archive");
 
             for result in reader.records() {
                 let record = result.expect("Could not read record");
@@ -56,10 +194,21 @@ fn main() {
                 let color_str = record.get(2).unwrap().to_string();
                 let parsed_color = colors_transform::Rgb::from_hex_str(&color_str).unwrap();
 
+                let color = [
+                    parsed_color.get_red() as u8,
+                    parsed_color.get_green() as u8,
+                    parsed_color.get_blue() as u8,
+                    0xff,
+                ];
+
                 let clean_coords = record.get(3).unwrap().replace('"', "");
-                // todo: handle moderator edits
                 if clean_coords.matches(',').count() != 1 {
-                    println!("Invalid coordinates: {}", clean_coords);
+                    match parse_moderator_edit(&clean_coords) {
+                        Some(shape) => archive_writer
+                            .add_moderator_edit(shape, color, placed_at)
+                            .expect("Could not add moderator edit"),
+                        None => println!("Invalid coordinates: {}", clean_coords),
+                    }
                     continue;
                 }
                 let mut coords = clean_coords.split(',');
@@ -67,51 +216,187 @@ fn main() {
                 let y_str = coords.next().unwrap();
                 let x = x_str.parse::<u16>().expect("Could not parse x coordinate");
                 let y = y_str.parse::<u16>().expect("Could not parse y coordinate");
-
-                archive_writer.add_tile(
-                    x,
-                    y,
-                    [
-                        parsed_color.get_red() as u8,
-                        parsed_color.get_green() as u8,
-                        parsed_color.get_blue() as u8,
-                        0xff,
-                    ],
-                    placed_at,
-                );
+                match users {
+                    true => archive_writer.add_tile_placed_by(
+                        x,
+                        y,
+                        color,
+                        placed_at,
+                        record.get(1).unwrap(),
+                    ),
+                    false => archive_writer.add_tile(x, y, color, placed_at),
+                }
+                .expect("Could not add tile");
             }
 
-            archive_writer.finalize(true);
+            archive_writer
+                .finalize()
+                .expect("Could not finalize archive");
         }
         Commands::Render {
             archive_path,
             out_file,
             up_to_seconds,
+            private_key,
         } => {
-            let file = File::open(archive_path).expect("Could not open file");
-            let reader = PlacedArchiveReader::new(file).expect("Could not read archive");
-            let canvas_size = reader.meta.get_largest_canvas_size().unwrap();
+            let mut reader = open_archive(&archive_path, private_key);
 
-            let mut canvas =
-                image::RgbaImage::new(canvas_size.width as u32, canvas_size.height as u32);
+            let up_to_ms = match up_to_seconds {
+                0 => reader.meta.last_tile_placed_at_ms_since_epoch,
+                _ => up_to_seconds as u64 * 1000,
+            };
 
-            canvas.fill(0xff);
-
-            for tile in reader {
-                if tile.ms_since_epoch > up_to_seconds * 1000 && up_to_seconds != 0 {
-                    break;
-                }
-
-                canvas.put_pixel(tile.x as u32, tile.y as u32, image::Rgba(tile.color));
-            }
+            let canvas = reader
+                .canvas_at(up_to_ms)
+                .expect("Could not reconstruct canvas");
 
             canvas.save(out_file).expect("Could not save image");
         }
         Commands::Play {
             archive_path,
             timescale_factor,
+            start_at_seconds,
+            private_key,
         } => {
-            player::play(archive_path, timescale_factor);
+            player::play(
+                archive_path,
+                timescale_factor,
+                start_at_seconds as u64 * 1000,
+                private_key.map(|path| StaticSecret::from(read_key_file(&path))),
+            );
         }
+        Commands::History {
+            archive_path,
+            x,
+            y,
+            width,
+            height,
+            json,
+            private_key,
+        } => {
+            let mut reader = open_archive(&archive_path, private_key);
+
+            let history = reader
+                .pixel_history(BoundingBox {
+                    min_x: x,
+                    min_y: y,
+                    max_x: x.saturating_add(width.max(1) - 1),
+                    max_y: y.saturating_add(height.max(1) - 1),
+                })
+                .expect("Could not read pixel history");
+
+            let rows = history.iter().map(|tile| {
+                let placed_at = match tile.placed_at(&reader.meta) {
+                    Some(placed_at) => placed_at.format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+                    None => format!("+{}ms", tile.ms_since_epoch),
+                };
+                let color = format!(
+                    "#{:02x}{:02x}{:02x}",
+                    tile.color[0], tile.color[1], tile.color[2]
+                );
+
+                (placed_at, tile, color)
+            });
+
+            if json {
+                let entries = rows
+                    .map(|(placed_at, tile, color)| {
+                        format!(
+                            "{{\"placed_at\":\"{}\",\"ms_since_epoch\":{},\"x\":{},\"y\":{},\"color\":\"{}\"}}",
+                            placed_at, tile.ms_since_epoch, tile.x, tile.y, color
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                println!("[{}]", entries.join(","));
+            } else {
+                println!("{:<24} {:>5} {:>5}  color", "placed at", "x", "y");
+                for (placed_at, tile, color) in rows {
+                    println!("{:<24} {:>5} {:>5}  {}", placed_at, tile.x, tile.y, color);
+                }
+            }
+        }
+        Commands::Verify {
+            archive_path,
+            private_key,
+        } => {
+            let mut reader = open_archive(&archive_path, private_key);
+
+            match reader.verify() {
+                Ok(()) => println!(
+                    "Archive is intact ({} chunks)",
+                    reader.meta.chunk_descs.len()
+                ),
+                Err(problems) => {
+                    for problem in &problems {
+                        println!("{:?}", problem);
+                    }
+                    println!("Found {} problems", problems.len());
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Keygen { out_file } => {
+            let private_key = StaticSecret::from(rand::random::<[u8; 32]>());
+            let public_key = PublicKey::from(&private_key);
+
+            std::fs::write(&out_file, private_key.to_bytes()).expect("Could not write private key");
+            std::fs::write(format!("{}.pub", out_file), public_key.as_bytes())
+                .expect("Could not write public key");
+        }
+    }
+}
+
+/// Opens an archive, decrypting it with the key in `private_key_path` if given.
+fn open_archive(
+    archive_path: &str,
+    private_key_path: Option<String>,
+) -> PlacedArchiveReader<'static, File> {
+    let file = File::open(archive_path).expect("Could not open file");
+
+    match private_key_path {
+        Some(path) => PlacedArchiveReader::with_private_keys(
+            file,
+            &[StaticSecret::from(read_key_file(&path))],
+        ),
+        None => PlacedArchiveReader::new(file),
+    }
+    .expect("Could not read archive")
+}
+
+/// Reads a key file, which holds the 32 raw bytes of an X25519 key.
+fn read_key_file(path: &str) -> [u8; 32] {
+    std::fs::read(path)
+        .expect("Could not read key file")
+        .try_into()
+        .expect("Key file must contain exactly 32 bytes")
+}
+
+/// Parses the coordinates of a moderator edit, either a rectangle ("x1,y1,x2,y2") or a circle ("{X: x, Y: y, R: radius}").
+fn parse_moderator_edit(coords: &str) -> Option<EditShape> {
+    if let Some(circle) = coords.strip_prefix('{').and_then(|c| c.strip_suffix('}')) {
+        let mut values = circle
+            .split(',')
+            .map(|field| field.split(':').nth(1)?.trim().parse::<u16>().ok());
+
+        return Some(EditShape::Circle {
+            x: values.next()??,
+            y: values.next()??,
+            radius: values.next()??,
+        });
+    }
+
+    let values = coords
+        .split(',')
+        .map(|value| value.trim().parse::<u16>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    match values[..] {
+        [x1, y1, x2, y2] => Some(EditShape::Rectangle(BoundingBox {
+            min_x: x1.min(x2),
+            min_y: y1.min(y2),
+            max_x: x1.max(x2),
+            max_y: y1.max(y2),
+        })),
+        _ => None,
     }
 }
@@ -1,4 +1,6 @@
-const SIZE_OF_COORDINATE_UPDATE_BYTES = 9u;
+const SIZE_OF_COORDINATE_UPDATE_BYTES = 14u;
+// Mirrors StoredTilePlacement::PADDING_COLOR_INDEX
+const PADDING_COLOR_INDEX = 0xFFFFu;
 
 struct FourTileUpdate {
   data: array<u32, SIZE_OF_COORDINATE_UPDATE_BYTES>
@@ -8,21 +10,26 @@ struct DecodedTileUpdate {
   x: u32,
   y: u32,
   color_index: u32,
+  // Relative to the batch's time base, see `Locals`
   ms_since_epoch: u32,
 };
 
 // todo: use https://docs.rs/crevice/latest/crevice/ for more ergonomic struct?
 struct Locals {
-  color_map: array<vec4<u32>, 256>,
   width: u32,
   height: u32,
+  // Time base of the current batch of tiles, as the low and high halves of a u64
+  base_ms_since_epoch_low: u32,
+  base_ms_since_epoch_high: u32,
 };
 
+// Times are relative to the batch's time base
 struct BoundsInChunk {
   requested_up_to_ms_since_epoch: u32,
   max_ms_since_epoch_seen: atomic<u32>,
   max_ms_since_epoch_used: atomic<u32>,
-  max_index_in_chunk_used: atomic<u32>,
+  // One more than the index of the last tile used
+  num_of_tiles_used: atomic<u32>,
 }
 
 @group(0) @binding(0) var<storage, read> tile_updates : array<FourTileUpdate>;
@@ -30,6 +37,7 @@ struct BoundsInChunk {
 @group(0) @binding(2) var<storage, read_write> last_index_for_tile : array<atomic<u32>>;
 @group(0) @binding(3) var<storage, read_write> bounds : BoundsInChunk;
 @group(0) @binding(4) var texture_out : texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(5) var<storage, read> color_map : array<vec4<u32>>;
 
 fn readU8(i: u32, current_offset: u32) -> u32 {
 	var ipos : u32 = current_offset / 4u;
@@ -65,9 +73,18 @@ fn readTile(four_tile_offset: u32, offset_in_four_tiles: u32) -> DecodedTileUpda
   current_offset += 2u;
   tile.y = readU16(four_tile_offset, current_offset);
   current_offset += 2u;
-  tile.color_index = readU8(four_tile_offset, current_offset);
-  current_offset += 1u;
-  tile.ms_since_epoch = readU32(four_tile_offset, current_offset);
+  tile.color_index = readU16(four_tile_offset, current_offset);
+  current_offset += 2u;
+  // Timestamps are stored as u64, so subtract the time base with a borrow from the high half
+  let ms_since_epoch_low = readU32(four_tile_offset, current_offset);
+  current_offset += 4u;
+  let ms_since_epoch_high = readU32(four_tile_offset, current_offset);
+  let borrow = select(0u, 1u, ms_since_epoch_low < r_locals.base_ms_since_epoch_low);
+  tile.ms_since_epoch = ms_since_epoch_low - r_locals.base_ms_since_epoch_low;
+  if (ms_since_epoch_high - r_locals.base_ms_since_epoch_high - borrow != 0u) {
+    // Only the ~49 days after the time base fit in a u32
+    tile.ms_since_epoch = 0xFFFFFFFFu;
+  }
 
   return tile;
 }
@@ -89,7 +106,7 @@ fn calculate_final_tiles(@builtin(global_invocation_id) id: vec3<u32>) {
 
   let tile = readTile(id.x, id.y);
 
-  if (tile.color_index == 255u) {
+  if (tile.color_index == PADDING_COLOR_INDEX) {
     // This update is just padding
     return;
   }
@@ -101,7 +118,7 @@ fn calculate_final_tiles(@builtin(global_invocation_id) id: vec3<u32>) {
   }
 
   atomicMax(&bounds.max_ms_since_epoch_used, tile.ms_since_epoch);
-  atomicMax(&bounds.max_index_in_chunk_used, getDataIndexForInvocation(id));
+  atomicMax(&bounds.num_of_tiles_used, getDataIndexForInvocation(id) + 1u);
   atomicMax(&last_index_for_tile[getTileIndex(tile)], getDataIndexForInvocation(id));
 }
 
@@ -114,7 +131,7 @@ fn update_texture(@builtin(global_invocation_id) id: vec3<u32>) {
 
   let tile = readTile(id.x, id.y);
 
-  if (tile.color_index == 255u) {
+  if (tile.color_index == PADDING_COLOR_INDEX) {
     // This update is just padding
     return;
   }
@@ -129,7 +146,7 @@ fn update_texture(@builtin(global_invocation_id) id: vec3<u32>) {
     return;
   }
 
-  let color = r_locals.color_map[tile.color_index];
+  let color = color_map[tile.color_index];
 
   textureStore(
     texture_out,
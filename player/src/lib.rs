@@ -1,10 +1,10 @@
 use std::{
     fs::File,
-    io::{BufReader, Cursor, Read, Seek},
+    io::{Read, Seek},
     time::Duration,
 };
 
-use archive::{structures::StoredTilePlacement, PlacedArchiveReader};
+use archive::{PlacedArchiveReader, StaticSecret};
 use game_loop::{game_loop, Time, TimeTrait};
 use winit::{
     dpi::{LogicalSize, PhysicalSize},
@@ -31,10 +31,11 @@ impl<R: Read + Seek> Player<R> {
         render_state: pixel_art_display_state::PixelArtDisplayState<R>,
         timescale_factor: f32,
         window_size: PhysicalSize<u32>,
+        start_at_ms: u64,
     ) -> Self {
-        let texture_size = render_state.texture_size.clone();
+        let texture_size = render_state.texture_size;
         Self {
-            rendered_up_to: Duration::ZERO,
+            rendered_up_to: Duration::from_millis(start_at_ms),
             render_state,
             timescale_factor,
             transform_generator: transform_generator::TransformGenerator::new(
@@ -49,7 +50,7 @@ impl<R: Read + Seek> Player<R> {
         self.rendered_up_to += dt * self.timescale_factor as u32;
 
         self.render_state
-            .update(self.rendered_up_to.as_millis() as u32);
+            .update(self.rendered_up_to.as_millis() as u64);
     }
 
     pub fn draw(&mut self) {
@@ -100,7 +101,12 @@ pub const TIME_STEP: Duration = Duration::from_nanos(1_000_000_000 / FPS as u64)
 const WIDTH: u32 = 2000;
 const HEIGHT: u32 = 2000;
 
-pub fn play(archive_path: String, timescale_factor: f32) -> i32 {
+pub fn play(
+    archive_path: String,
+    timescale_factor: f32,
+    start_at_ms: u64,
+    private_key: Option<StaticSecret>,
+) -> i32 {
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
 
@@ -115,12 +121,24 @@ pub fn play(archive_path: String, timescale_factor: f32) -> i32 {
     };
 
     let file = File::open(archive_path).expect("Failed to open archive");
-    let reader = PlacedArchiveReader::new(file).expect("Failed to create reader");
+    let mut reader = match private_key {
+        Some(private_key) => PlacedArchiveReader::with_private_keys(file, &[private_key]),
+        None => PlacedArchiveReader::new(file),
+    }
+    .expect("Failed to create reader");
+    let canvas = reader
+        .canvas_at(start_at_ms)
+        .expect("Failed to reconstruct starting canvas");
 
     let mut state =
         pixel_art_display_state::PixelArtDisplayState::new(&window, reader.meta.clone(), reader);
-    state.clear(wgpu::Color::WHITE);
-    let p = Player::new(state, timescale_factor, window.inner_size());
+    state.set_canvas(
+        canvas.as_raw(),
+        canvas.width(),
+        canvas.height(),
+        start_at_ms,
+    );
+    let p = Player::new(state, timescale_factor, window.inner_size(), start_at_ms);
 
     game_loop(
         event_loop,
@@ -144,8 +162,8 @@ pub fn play(archive_path: String, timescale_factor: f32) -> i32 {
                 g.game.handle_input(&input);
             }
 
-            match event {
-                Event::WindowEvent { event, .. } => match event {
+            if let Event::WindowEvent { event, .. } = event {
+                match event {
                     WindowEvent::Resized(physical_size) => {
                         g.game.resize(*physical_size);
                     }
@@ -161,8 +179,7 @@ pub fn play(archive_path: String, timescale_factor: f32) -> i32 {
                         std::process::exit(0);
                     }
                     _ => (),
-                },
-                _ => {}
+                }
             };
         },
     );
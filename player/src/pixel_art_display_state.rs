@@ -21,8 +21,8 @@ pub struct PixelArtDisplayState<R> {
     /// A default renderer to scale the input texture to the screen size (stolen from the pixels crate)
     scaling_renderer: ScalingRenderer,
     compute_renderer: TextureUpdateByCoords<R>,
-    last_up_to_ms: u32,
-    up_to_ms: u32,
+    last_up_to_ms: u64,
+    up_to_ms: u64,
 
     pub texture_size: wgpu::Extent3d,
 }
@@ -107,11 +107,11 @@ impl<R: Read + Seek> PixelArtDisplayState<R> {
         }
     }
 
-    pub fn update(&mut self, up_to_ms: u32) {
+    pub fn update(&mut self, up_to_ms: u64) {
         self.last_up_to_ms = self.up_to_ms;
         self.up_to_ms = up_to_ms;
 
-        let diff = Duration::from_millis((self.up_to_ms - self.last_up_to_ms).into());
+        let diff = Duration::from_millis(self.up_to_ms - self.last_up_to_ms);
 
         match self
             .compute_renderer
@@ -122,12 +122,21 @@ impl<R: Read + Seek> PixelArtDisplayState<R> {
                 panic!("Reached end of input");
             }
             PartialUpdateResult::UpdatedUpToMs {
-                max_ms_since_epoch_used,
-                did_update_up_to_requested_ms,
+                max_ms_since_epoch_used: _,
+                did_update_up_to_requested_ms: _,
             } => {}
         }
     }
 
+    /// Replaces the displayed canvas with `canvas` (tightly packed RGBA pixels of a `width` by `height` canvas), as it was at `up_to_ms`.
+    /// Subsequent updates continue from there, so the underlying reader should be positioned at the first tile placed after `up_to_ms`.
+    pub fn set_canvas(&mut self, canvas: &[u8], width: u32, height: u32, up_to_ms: u64) {
+        self.compute_renderer
+            .write_canvas(&self.queue, canvas, width, height);
+        self.last_up_to_ms = up_to_ms;
+        self.up_to_ms = up_to_ms;
+    }
+
     pub fn render(&mut self, transform: Mat4) {
         let frame = self.surface.get_current_texture().unwrap();
 
@@ -150,31 +159,6 @@ impl<R: Read + Seek> PixelArtDisplayState<R> {
         frame.present();
     }
 
-    pub fn clear(&mut self, color: wgpu::Color) {
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("clear_encoder"),
-            });
-
-        {
-            let _render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Clear render pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &self.compute_renderer.texture_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(color),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
-        }
-
-        self.queue.submit(std::iter::once(encoder.finish()));
-    }
-
     pub fn on_window_resize(&mut self, new_width: u32, new_height: u32) {
         self.surface.configure(
             &self.device,
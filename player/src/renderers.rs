@@ -1,13 +1,6 @@
 use ultraviolet::Mat4;
 use wgpu::util::DeviceExt;
 
-/// A logical texture size for a window surface.
-#[derive(Debug)]
-pub struct SurfaceSize {
-    pub width: u32,
-    pub height: u32,
-}
-
 /// The default renderer that scales your frame to the screen size.
 #[derive(Debug)]
 pub struct ScalingRenderer {
@@ -1,7 +1,6 @@
 use std::{
-    io::{Cursor, Read, Seek, SeekFrom, Write},
+    io::{Cursor, Read, Seek, SeekFrom},
     num::{NonZeroU32, NonZeroU64},
-    sync::mpsc,
     time::Duration,
     vec,
 };
@@ -14,7 +13,7 @@ use wgpu::{util::DeviceExt, COPY_BUFFER_ALIGNMENT};
 pub enum PartialUpdateResult {
     ReachedEndOfInput,
     UpdatedUpToMs {
-        max_ms_since_epoch_used: u32,
+        max_ms_since_epoch_used: u64,
         // todo: rename?
         did_update_up_to_requested_ms: bool,
     },
@@ -35,10 +34,7 @@ impl Helpers {
                 * StoredTilePlacement::encoded_size() as u64,
         );
 
-        let max_in_bytes =
-            absolute_max_in_bytes - (absolute_max_in_bytes % Helpers::get_alignment_factor());
-
-        max_in_bytes
+        absolute_max_in_bytes - (absolute_max_in_bytes % Helpers::get_alignment_factor())
     }
 
     fn get_aligned_input_size(device: &wgpu::Device, min_size_in_bytes: u64) -> u64 {
@@ -52,17 +48,19 @@ impl Helpers {
     fn get_alignment_factor() -> u64 {
         lcm(
             StoredTilePlacement::encoded_size() as u64 * NUM_OF_TILES_PER_WORKGROUP as u64,
-            COPY_BUFFER_ALIGNMENT as u64,
+            COPY_BUFFER_ALIGNMENT,
         )
     }
 }
 
 #[derive(Debug)]
 struct ComputedBounds {
+    // Mirrors the layout of the GPU-side struct
+    #[allow(dead_code)]
     requested_up_to_ms_since_epoch: u32,
     max_ms_since_epoch_seen: u32,
     max_ms_since_epoch_used: u32,
-    max_index_in_chunk_used: u32,
+    num_of_tiles_used: u32,
 }
 
 pub struct TextureUpdateByCoords<R> {
@@ -72,6 +70,7 @@ pub struct TextureUpdateByCoords<R> {
     texture_extent: wgpu::Extent3d,
     pub texture_view: wgpu::TextureView,
     bounds_buffer: wgpu::Buffer,
+    locals_buffer: wgpu::Buffer,
     input_buffer: wgpu::Buffer,
     zeros_buffer: wgpu::Buffer,
     calculate_final_tiles_pipeline: wgpu::ComputePipeline,
@@ -138,24 +137,29 @@ impl<R: Read + Seek> TextureUpdateByCoords<R> {
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
 
-        let mut r: Vec<[u32; 4]> = meta
+        let mut color_map: Vec<[u32; 4]> = meta
             .clone()
             .color_id_to_tuple
             .into_values()
             .map(|x| [x[0] as u32, x[1] as u32, x[2] as u32, x[3] as u32])
             .collect();
 
-        // Pad to 256 color tuples
-        while r.len() < 256 {
-            r.push([0, 0, 0, 0]);
+        // Storage buffers can't be empty
+        if color_map.is_empty() {
+            color_map.push([0, 0, 0, 0]);
         }
 
+        let color_map_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("texture_update_by_coords color map buffer"),
+            contents: bytemuck::cast_slice(&color_map),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
         let size = meta.get_largest_canvas_size().unwrap();
 
-        let mut r = r.into_iter().flatten().collect::<Vec<u32>>();
-        // Padding for alignment
-        r.append(&mut vec![size.width.into(), size.height.into()]);
-        r.append(&mut vec![0; 2]);
+        // The time base of each batch of tiles is set before it's processed
+        let mut r = vec![size.width.into(), size.height.into()];
+        r.append(&mut vec![0u32; 2]);
 
         let locals_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("texture_update_by_coords locals buffer"),
@@ -179,6 +183,7 @@ impl<R: Read + Seek> TextureUpdateByCoords<R> {
             usage: wgpu::TextureUsages::TEXTURE_BINDING
                 | wgpu::TextureUsages::STORAGE_BINDING
                 | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_DST
                 | texture_usages.unwrap_or(wgpu::TextureUsages::empty()),
             label: None,
         };
@@ -257,6 +262,10 @@ impl<R: Read + Seek> TextureUpdateByCoords<R> {
                     binding: 4,
                     resource: wgpu::BindingResource::TextureView(&some_view),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: color_map_buffer.as_entire_binding(),
+                },
             ],
         });
 
@@ -264,6 +273,7 @@ impl<R: Read + Seek> TextureUpdateByCoords<R> {
             reader,
             meta,
             bounds_buffer,
+            locals_buffer,
             input_buffer,
             texture,
             texture_extent,
@@ -276,10 +286,34 @@ impl<R: Read + Seek> TextureUpdateByCoords<R> {
             last_index_for_tile,
             staging_buffer,
             // todo: use correct chunk size
-            staging_belt: wgpu::util::StagingBelt::new(Helpers::get_max_input_size(device) as u64),
+            staging_belt: wgpu::util::StagingBelt::new(Helpers::get_max_input_size(device)),
         }
     }
 
+    /// Overwrites the top left of the texture with `canvas`, which must contain tightly packed RGBA pixels for a canvas of
+    /// `width` by `height`. The texture is sized for the largest canvas in the archive, so the canvas may not cover all of it.
+    pub fn write_canvas(&self, queue: &wgpu::Queue, canvas: &[u8], width: u32, height: u32) {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            canvas,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * width),
+                rows_per_image: NonZeroU32::new(height),
+            },
+            wgpu::Extent3d {
+                width: width.min(self.texture_extent.width),
+                height: height.min(self.texture_extent.height),
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
     /// Make sure to only pass one tile per position, as it's not guaranteed that the order of tiles will be preserved during rendering.
     /// todo: add note about calling only once per frame
     /// `duration` is used as a performance hint.
@@ -287,7 +321,7 @@ impl<R: Read + Seek> TextureUpdateByCoords<R> {
         &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        up_to_ms: u32,
+        up_to_ms: u64,
         duration: Duration,
     ) -> PartialUpdateResult {
         loop {
@@ -314,13 +348,41 @@ impl<R: Read + Seek> TextureUpdateByCoords<R> {
         &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        up_to_ms: u32,
+        up_to_ms: u64,
         duration: Duration,
     ) -> PartialUpdateResult {
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("texture_update_by_coords encoder"),
         });
 
+        let (bytes_written, first_ms_since_epoch) = self
+            .write_next_input_chunk(&mut encoder, device, duration)
+            .unwrap();
+
+        if bytes_written == 0 {
+            self.staging_belt.finish();
+            queue.submit(Some(encoder.finish()));
+            return PartialUpdateResult::ReachedEndOfInput;
+        }
+
+        // The shader compares times relative to just before the first tile of the batch, as it only supports 32-bit
+        // integers. Only batches spanning more than ~49 days don't fit, and the rest of those is left for the next batch.
+        let base_ms_since_epoch = first_ms_since_epoch.saturating_sub(1);
+        let requested_up_to_ms = up_to_ms
+            .saturating_sub(base_ms_since_epoch)
+            .min(u32::MAX as u64 - 1) as u32;
+
+        {
+            let mut locals_mut = self.staging_belt.write_buffer(
+                &mut encoder,
+                &self.locals_buffer,
+                8,
+                NonZeroU64::new(8).unwrap(),
+                device,
+            );
+            locals_mut.copy_from_slice(&base_ms_since_epoch.to_le_bytes());
+        }
+
         {
             let mut bounds_mut = self.staging_belt.write_buffer(
                 &mut encoder,
@@ -329,22 +391,13 @@ impl<R: Read + Seek> TextureUpdateByCoords<R> {
                 NonZeroU64::new(self.bounds_buffer.size()).unwrap(),
                 device,
             );
-            bytemuck::cast_slice_mut::<u8, u32>(&mut bounds_mut)[0] = up_to_ms;
+            bytemuck::cast_slice_mut::<u8, u32>(&mut bounds_mut)[0] = requested_up_to_ms;
             bytemuck::cast_slice_mut::<u8, u32>(&mut bounds_mut)[1] = 0;
             bytemuck::cast_slice_mut::<u8, u32>(&mut bounds_mut)[2] = 0;
             bytemuck::cast_slice_mut::<u8, u32>(&mut bounds_mut)[3] = 0;
         }
-
-        let bytes_written = self
-            .write_next_input_chunk(&mut encoder, device, duration)
-            .unwrap();
         self.staging_belt.finish();
 
-        if bytes_written == 0 {
-            queue.submit(Some(encoder.finish()));
-            return PartialUpdateResult::ReachedEndOfInput;
-        }
-
         let num_of_tiles = bytes_written / StoredTilePlacement::encoded_size();
 
         let num_of_workgroups =
@@ -390,29 +443,33 @@ impl<R: Read + Seek> TextureUpdateByCoords<R> {
         queue.submit(Some(encoder.finish()));
         self.staging_belt.recall();
 
-        let bounds = self.read_computed_bounds(&device).await;
+        let bounds = self.read_computed_bounds(device).await;
 
-        if bounds.max_index_in_chunk_used != (num_of_tiles as u32 - 1) {
+        // Continue from the first tile that wasn't used, so the next batch starts with it as its time base
+        if bounds.num_of_tiles_used != num_of_tiles as u32 {
             self.reader
                 .seek(SeekFrom::Current(
-                    -((num_of_tiles as i64 - bounds.max_index_in_chunk_used as i64)
+                    -((num_of_tiles as i64 - bounds.num_of_tiles_used as i64)
                         * StoredTilePlacement::encoded_size() as i64),
                 ))
                 .unwrap();
         }
 
-        return PartialUpdateResult::UpdatedUpToMs {
-            max_ms_since_epoch_used: bounds.max_ms_since_epoch_used,
-            did_update_up_to_requested_ms: bounds.max_ms_since_epoch_seen >= up_to_ms,
-        };
+        PartialUpdateResult::UpdatedUpToMs {
+            max_ms_since_epoch_used: base_ms_since_epoch + bounds.max_ms_since_epoch_used as u64,
+            did_update_up_to_requested_ms: base_ms_since_epoch
+                + bounds.max_ms_since_epoch_seen as u64
+                >= up_to_ms,
+        }
     }
 
+    /// Returns the number of bytes written, and the time the first tile written was placed at.
     fn write_next_input_chunk(
         &mut self,
         encoder: &mut wgpu::CommandEncoder,
         device: &wgpu::Device,
         duration: Duration,
-    ) -> std::io::Result<usize> {
+    ) -> std::io::Result<(usize, u64)> {
         let estimated_num_of_tiles = self.get_estimated_num_of_tiles_for_duration(duration);
         let copy_size = Helpers::get_aligned_input_size(
             device,
@@ -438,9 +495,13 @@ impl<R: Read + Seek> TextureUpdateByCoords<R> {
         ) {
             Ok(num_written) => {
                 if num_written == 0 {
-                    return Ok(0);
+                    return Ok((0, 0));
                 }
 
+                // Follows the tile's x, y and color index
+                let first_ms_since_epoch =
+                    u64::from_le_bytes(tracked_writer.get_ref()[6..14].try_into().unwrap());
+
                 let mut padding_count = 0;
                 // Pad
                 while (num_written + padding_count)
@@ -451,7 +512,7 @@ impl<R: Read + Seek> TextureUpdateByCoords<R> {
                     StoredTilePlacement {
                         x: 0,
                         y: 0,
-                        color_index: 255,
+                        color_index: StoredTilePlacement::PADDING_COLOR_INDEX,
                         ms_since_epoch: 0,
                     }
                     .write_into(&mut tracked_writer);
@@ -459,7 +520,7 @@ impl<R: Read + Seek> TextureUpdateByCoords<R> {
                     padding_count += StoredTilePlacement::encoded_size() as u64;
                 }
 
-                Ok(num_written as usize)
+                Ok((num_written as usize, first_ms_since_epoch))
             }
             Err(err) => Err(err),
         }
@@ -481,13 +542,13 @@ impl<R: Read + Seek> TextureUpdateByCoords<R> {
             requested_up_to_ms_since_epoch: cast_data[0],
             max_ms_since_epoch_seen: cast_data[1],
             max_ms_since_epoch_used: cast_data[2],
-            max_index_in_chunk_used: cast_data[3],
+            num_of_tiles_used: cast_data[3],
         };
 
         drop(data);
         self.staging_buffer.unmap();
 
-        return bounds;
+        bounds
     }
 
     fn get_estimated_num_of_tiles_for_duration(&self, duration: Duration) -> u64 {
@@ -495,16 +556,20 @@ impl<R: Read + Seek> TextureUpdateByCoords<R> {
             // Add 1 to prevent division by 0
             / (self.meta.last_tile_placed_at_ms_since_epoch as f64 + 1.0);
 
-        let estimated_num_of_tiles =
-            (average_tiles_placed_per_ms * duration.as_millis() as f64) as u64;
-
-        return estimated_num_of_tiles;
+        (average_tiles_placed_per_ms * duration.as_millis() as f64) as u64
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use archive::structures::{CanvasSizeChange, Meta, StoredTilePlacement};
+    use archive::{
+        structures::{
+            CanvasSizeChange, ChunkingStrategy, Compression, Meta, StoredTilePlacement,
+            TileEncoding,
+        },
+        PlacedArchiveReader, PlacedArchiveWriter, PlacedArchiveWriterOptions,
+    };
+    use chrono::NaiveDateTime;
     use image::{ImageBuffer, Rgba};
     use log::{log_enabled, Level};
     use rand::Rng;
@@ -528,7 +593,7 @@ mod tests {
             test_name: &str,
             meta: Meta,
             data: Vec<u8>,
-            up_to_ms: u32,
+            up_to_ms: u64,
         ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
             let (device, queue) = Self::get_device();
 
@@ -578,14 +643,14 @@ mod tests {
             let mut encoder =
                 device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-            let bytes_per_row = (u32_size * texture_extent.width)
-                + (COPY_BYTES_PER_ROW_ALIGNMENT - 1)
+            let bytes_per_row = ((u32_size * texture_extent.width)
+                + (COPY_BYTES_PER_ROW_ALIGNMENT - 1))
                 & !(COPY_BYTES_PER_ROW_ALIGNMENT - 1);
 
             encoder.copy_texture_to_buffer(
                 wgpu::ImageCopyTexture {
                     aspect: wgpu::TextureAspect::All,
-                    texture: &texture,
+                    texture,
                     mip_level: 0,
                     origin: wgpu::Origin3d::ZERO,
                 },
@@ -626,15 +691,13 @@ mod tests {
                 data = repacked_data;
             }
 
-            let buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(
+            ImageBuffer::<Rgba<u8>, _>::from_raw(
                 texture_extent.width,
                 texture_extent.height,
                 // copy data to avoid dealing with lifetimes
                 data.to_vec(),
             )
-            .unwrap();
-
-            buffer
+            .unwrap()
         }
 
         pub fn get_device() -> (Device, wgpu::Queue) {
@@ -694,7 +757,13 @@ mod tests {
         }
 
         let meta = Meta {
+            first_tile_placed_at_ms_since_unix_epoch: None,
             chunk_descs: vec![],
+            num_users: None,
+            moderator_edits: vec![],
+            chunking_strategy: ChunkingStrategy::default(),
+            compression: Compression::default(),
+            tile_encoding: TileEncoding::default(),
             color_id_to_tuple,
             last_tile_placed_at_ms_since_epoch: 0,
             total_tile_placements: data.len() as u64 / StoredTilePlacement::encoded_size() as u64,
@@ -743,7 +812,13 @@ mod tests {
         }
 
         let meta = Meta {
+            first_tile_placed_at_ms_since_unix_epoch: None,
             chunk_descs: vec![],
+            num_users: None,
+            moderator_edits: vec![],
+            chunking_strategy: ChunkingStrategy::default(),
+            compression: Compression::default(),
+            tile_encoding: TileEncoding::default(),
             color_id_to_tuple,
             last_tile_placed_at_ms_since_epoch: 0,
             total_tile_placements: data.len() as u64 / StoredTilePlacement::encoded_size() as u64,
@@ -803,7 +878,13 @@ mod tests {
         }
 
         let meta = Meta {
+            first_tile_placed_at_ms_since_unix_epoch: None,
             chunk_descs: vec![],
+            num_users: None,
+            moderator_edits: vec![],
+            chunking_strategy: ChunkingStrategy::default(),
+            compression: Compression::default(),
+            tile_encoding: TileEncoding::default(),
             color_id_to_tuple,
             last_tile_placed_at_ms_since_epoch: 2,
             total_tile_placements: data.len() as u64 / StoredTilePlacement::encoded_size() as u64,
@@ -842,7 +923,13 @@ mod tests {
         .write_into(&mut data);
 
         let meta = Meta {
+            first_tile_placed_at_ms_since_unix_epoch: None,
             chunk_descs: vec![],
+            num_users: None,
+            moderator_edits: vec![],
+            chunking_strategy: ChunkingStrategy::default(),
+            compression: Compression::default(),
+            tile_encoding: TileEncoding::default(),
             color_id_to_tuple,
             last_tile_placed_at_ms_since_epoch: 0,
             total_tile_placements: data.len() as u64 / StoredTilePlacement::encoded_size() as u64,
@@ -883,7 +970,7 @@ mod tests {
                 let tile = StoredTilePlacement {
                     x: x as u16,
                     y: y as u16,
-                    color_index: (x % 3) as u8,
+                    color_index: (x % 3) as u16,
                     ms_since_epoch: 0,
                 };
 
@@ -892,7 +979,13 @@ mod tests {
         }
 
         let meta = Meta {
+            first_tile_placed_at_ms_since_unix_epoch: None,
             chunk_descs: vec![],
+            num_users: None,
+            moderator_edits: vec![],
+            chunking_strategy: ChunkingStrategy::default(),
+            compression: Compression::default(),
+            tile_encoding: TileEncoding::default(),
             color_id_to_tuple,
             last_tile_placed_at_ms_since_epoch: 0,
             total_tile_placements: data.len() as u64 / StoredTilePlacement::encoded_size() as u64,
@@ -940,7 +1033,13 @@ mod tests {
         }
 
         let meta = Meta {
+            first_tile_placed_at_ms_since_unix_epoch: None,
             chunk_descs: vec![],
+            num_users: None,
+            moderator_edits: vec![],
+            chunking_strategy: ChunkingStrategy::default(),
+            compression: Compression::default(),
+            tile_encoding: TileEncoding::default(),
             color_id_to_tuple,
             last_tile_placed_at_ms_since_epoch: 0,
             total_tile_placements: data.len() as u64 / StoredTilePlacement::encoded_size() as u64,
@@ -996,7 +1095,13 @@ mod tests {
         }
 
         let meta = Meta {
+            first_tile_placed_at_ms_since_unix_epoch: None,
             chunk_descs: vec![],
+            num_users: None,
+            moderator_edits: vec![],
+            chunking_strategy: ChunkingStrategy::default(),
+            compression: Compression::default(),
+            tile_encoding: TileEncoding::default(),
             color_id_to_tuple,
             last_tile_placed_at_ms_since_epoch: 0,
             total_tile_placements: data.len() as u64 / StoredTilePlacement::encoded_size() as u64,
@@ -1068,7 +1173,13 @@ mod tests {
         }
 
         let meta = Meta {
+            first_tile_placed_at_ms_since_unix_epoch: None,
             chunk_descs: vec![],
+            num_users: None,
+            moderator_edits: vec![],
+            chunking_strategy: ChunkingStrategy::default(),
+            compression: Compression::default(),
+            tile_encoding: TileEncoding::default(),
             color_id_to_tuple: color_id_to_tuple.clone(),
             last_tile_placed_at_ms_since_epoch: 0,
             total_tile_placements: data.len() as u64 / StoredTilePlacement::encoded_size() as u64,
@@ -1096,7 +1207,8 @@ mod tests {
 
         let mut generator = rand::thread_rng();
 
-        for i in 0..128 {
+        // More colors than fit in an 8-bit index
+        for i in 0..1000 {
             color_id_to_tuple.insert(
                 i,
                 [
@@ -1110,8 +1222,11 @@ mod tests {
 
         let texture_size: u32 = 2000;
 
-        let mut expected_texture: Vec<Vec<u8>> =
-            vec![vec![0xff; texture_size as usize]; texture_size as usize];
+        let mut expected_texture: Vec<Vec<u16>> =
+            vec![
+                vec![StoredTilePlacement::PADDING_COLOR_INDEX; texture_size as usize];
+                texture_size as usize
+            ];
 
         let mut data = Vec::new();
 
@@ -1121,11 +1236,11 @@ mod tests {
                 let y = generator.gen_range(0..texture_size);
 
                 for _ in 0..5 {
-                    let color_index = generator.gen_range(0..color_id_to_tuple.len()) as u8;
+                    let color_index = generator.gen_range(0..color_id_to_tuple.len()) as u16;
                     StoredTilePlacement {
                         x: x as u16,
                         y: y as u16,
-                        color_index: color_index,
+                        color_index,
                         ms_since_epoch: i,
                     }
                     .write_into(&mut data);
@@ -1136,7 +1251,13 @@ mod tests {
         }
 
         let meta = Meta {
+            first_tile_placed_at_ms_since_unix_epoch: None,
             chunk_descs: vec![],
+            num_users: None,
+            moderator_edits: vec![],
+            chunking_strategy: ChunkingStrategy::default(),
+            compression: Compression::default(),
+            tile_encoding: TileEncoding::default(),
             color_id_to_tuple: color_id_to_tuple.clone(),
             last_tile_placed_at_ms_since_epoch: 99,
             total_tile_placements: data.len() as u64 / StoredTilePlacement::encoded_size() as u64,
@@ -1192,7 +1313,7 @@ mod tests {
         for x in 0..texture_size {
             for y in 0..texture_size {
                 let expected_color_key = expected_texture[x as usize][y as usize];
-                if expected_color_key == 0xff {
+                if expected_color_key == StoredTilePlacement::PADDING_COLOR_INDEX {
                     // This tile wasn't updated, so it should be equal to the color we cleared with
                     assert_eq!(buffer.get_pixel(x, y), &Rgba([0, 0, 0, 0]));
                     continue;
@@ -1225,7 +1346,7 @@ mod tests {
                     x: i as u16,
                     y: i as u16,
                     color_index: 0,
-                    ms_since_epoch: i as u32,
+                    ms_since_epoch: i as u64,
                 };
 
                 tile.write_into(&mut data);
@@ -1233,9 +1354,15 @@ mod tests {
         }
 
         let meta = Meta {
+            first_tile_placed_at_ms_since_unix_epoch: None,
             chunk_descs: vec![],
+            num_users: None,
+            moderator_edits: vec![],
+            chunking_strategy: ChunkingStrategy::default(),
+            compression: Compression::default(),
+            tile_encoding: TileEncoding::default(),
             color_id_to_tuple,
-            last_tile_placed_at_ms_since_epoch: texture_size - 1,
+            last_tile_placed_at_ms_since_epoch: texture_size as u64 - 1,
             total_tile_placements: data.len() as u64 / StoredTilePlacement::encoded_size() as u64,
             canvas_size_changes: vec![CanvasSizeChange {
                 width: texture_size as u16,
@@ -1304,7 +1431,7 @@ mod tests {
             }
         }
 
-        let result = controller.update(&device, &queue, texture_size, Duration::MAX);
+        let result = controller.update(&device, &queue, texture_size as u64, Duration::MAX);
         assert!(matches!(result, PartialUpdateResult::ReachedEndOfInput));
 
         let buffer = TestHelpers::texture_to_buffer(
@@ -1325,6 +1452,73 @@ mod tests {
         }
     }
 
+    #[test]
+    fn up_to_ms_past_u32() {
+        let mut color_id_to_tuple = BTreeMap::new();
+        color_id_to_tuple.insert(0, [0, 0, 0, 255]);
+
+        let texture_size: u32 = 16;
+        // More milliseconds than fit in 32 bits
+        let sixty_days_ms = 60 * 24 * 60 * 60 * 1000;
+
+        let mut data: Vec<u8> = Vec::new();
+        for (i, ms_since_epoch) in [0, sixty_days_ms, sixty_days_ms + 1000].iter().enumerate() {
+            StoredTilePlacement {
+                x: i as u16,
+                y: i as u16,
+                color_index: 0,
+                ms_since_epoch: *ms_since_epoch,
+            }
+            .write_into(&mut data);
+        }
+
+        let meta = Meta {
+            first_tile_placed_at_ms_since_unix_epoch: None,
+            chunk_descs: vec![],
+            num_users: None,
+            moderator_edits: vec![],
+            chunking_strategy: ChunkingStrategy::default(),
+            compression: Compression::default(),
+            tile_encoding: TileEncoding::default(),
+            color_id_to_tuple,
+            last_tile_placed_at_ms_since_epoch: sixty_days_ms + 1000,
+            total_tile_placements: 3,
+            canvas_size_changes: vec![CanvasSizeChange {
+                width: texture_size as u16,
+                height: texture_size as u16,
+                ms_since_epoch: 0,
+            }],
+        };
+
+        let (device, queue) = TestHelpers::get_device();
+        let mut controller = TextureUpdateByCoords::new(
+            &device,
+            meta,
+            Cursor::new(data),
+            Some(wgpu::TextureUsages::COPY_SRC),
+        );
+
+        let result = controller.update(&device, &queue, sixty_days_ms + 500, Duration::MAX);
+        assert!(matches!(
+            result,
+            PartialUpdateResult::UpdatedUpToMs {
+                max_ms_since_epoch_used,
+                did_update_up_to_requested_ms: true
+            } if max_ms_since_epoch_used == sixty_days_ms
+        ));
+
+        let buffer = TestHelpers::texture_to_buffer(
+            &device,
+            &queue,
+            &controller.texture,
+            controller.texture_extent,
+        );
+        TestHelpers::save_debug_image("up_to_ms_past_u32", &buffer);
+        assert_eq!(buffer.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(buffer.get_pixel(1, 1), &Rgba([0, 0, 0, 255]));
+        assert_eq!(buffer.get_pixel(2, 2), &Rgba([0, 0, 0, 0]));
+    }
+
     #[test]
     fn up_to_ms_with_holes() {
         let mut color_id_to_tuple = BTreeMap::new();
@@ -1343,16 +1537,22 @@ mod tests {
                 x: i as u16,
                 y: i as u16,
                 color_index: 0,
-                ms_since_epoch: i as u32,
+                ms_since_epoch: i as u64,
             };
 
             tile.write_into(&mut data);
         }
 
         let meta = Meta {
+            first_tile_placed_at_ms_since_unix_epoch: None,
             chunk_descs: vec![],
+            num_users: None,
+            moderator_edits: vec![],
+            chunking_strategy: ChunkingStrategy::default(),
+            compression: Compression::default(),
+            tile_encoding: TileEncoding::default(),
             color_id_to_tuple,
-            last_tile_placed_at_ms_since_epoch: texture_size - 1,
+            last_tile_placed_at_ms_since_epoch: texture_size as u64 - 1,
             total_tile_placements: data.len() as u64 / StoredTilePlacement::encoded_size() as u64,
             canvas_size_changes: vec![CanvasSizeChange {
                 width: texture_size as u16,
@@ -1396,4 +1596,124 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn write_canvas() {
+        let mut color_id_to_tuple = BTreeMap::new();
+        color_id_to_tuple.insert(0, [0, 0, 0, 255]);
+
+        let texture_size: u32 = 16;
+
+        let mut data: Vec<u8> = Vec::new();
+        StoredTilePlacement {
+            x: 1,
+            y: 1,
+            color_index: 0,
+            ms_since_epoch: 0,
+        }
+        .write_into(&mut data);
+
+        let meta = Meta {
+            first_tile_placed_at_ms_since_unix_epoch: None,
+            chunk_descs: vec![],
+            num_users: None,
+            moderator_edits: vec![],
+            chunking_strategy: ChunkingStrategy::default(),
+            compression: Compression::default(),
+            tile_encoding: TileEncoding::default(),
+            color_id_to_tuple,
+            last_tile_placed_at_ms_since_epoch: 0,
+            total_tile_placements: 1,
+            canvas_size_changes: vec![CanvasSizeChange {
+                width: texture_size as u16,
+                height: texture_size as u16,
+                ms_since_epoch: 0,
+            }],
+        };
+
+        let (device, queue) = TestHelpers::get_device();
+        let mut controller = TextureUpdateByCoords::new(
+            &device,
+            meta,
+            Cursor::new(data),
+            Some(wgpu::TextureUsages::COPY_SRC),
+        );
+
+        let canvas = [255, 0, 0, 255].repeat((texture_size * texture_size) as usize);
+        controller.write_canvas(&queue, &canvas, texture_size, texture_size);
+        controller.update(&device, &queue, 0, Duration::from_secs(1));
+
+        let buffer = TestHelpers::texture_to_buffer(
+            &device,
+            &queue,
+            &controller.texture,
+            controller.texture_extent,
+        );
+        TestHelpers::save_debug_image("write_canvas", &buffer);
+        for x in 0..texture_size {
+            for y in 0..texture_size {
+                if x == 1 && y == 1 {
+                    assert_eq!(buffer.get_pixel(x, y), &Rgba([0, 0, 0, 255]));
+                } else {
+                    assert_eq!(buffer.get_pixel(x, y), &Rgba([255, 0, 0, 255]));
+                }
+            }
+        }
+    }
+    #[test]
+    fn write_canvas_smaller_than_texture() {
+        let mut archive = Vec::new();
+        let mut archive_writer = PlacedArchiveWriter::with_options(
+            &mut archive,
+            PlacedArchiveWriterOptions {
+                chunking_strategy: ChunkingStrategy::TileCount(1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let placed_at = |ms| NaiveDateTime::from_timestamp_millis(ms).unwrap();
+        archive_writer
+            .add_canvas_size_change(4, 4, placed_at(0))
+            .unwrap();
+        archive_writer
+            .add_canvas_size_change(8, 8, placed_at(10))
+            .unwrap();
+        archive_writer
+            .add_tile(1, 1, [255, 0, 0, 255], placed_at(0))
+            .unwrap();
+        archive_writer
+            .add_tile(7, 7, [0, 0, 255, 255], placed_at(10))
+            .unwrap();
+        archive_writer.finalize().unwrap();
+        drop(archive_writer);
+
+        // The starting canvas is smaller than the texture, which is sized for the largest canvas
+        let mut reader = PlacedArchiveReader::new(Cursor::new(archive)).unwrap();
+        let canvas = reader.canvas_at(5).unwrap();
+        assert_eq!(canvas.dimensions(), (4, 4));
+
+        let (device, queue) = TestHelpers::get_device();
+        let mut controller = TextureUpdateByCoords::new(
+            &device,
+            reader.meta.clone(),
+            reader,
+            Some(wgpu::TextureUsages::COPY_SRC),
+        );
+        assert_eq!(controller.texture_extent.width, 8);
+
+        controller.write_canvas(&queue, canvas.as_raw(), canvas.width(), canvas.height());
+        controller.update(&device, &queue, 10, Duration::from_secs(1));
+
+        let buffer = TestHelpers::texture_to_buffer(
+            &device,
+            &queue,
+            &controller.texture,
+            controller.texture_extent,
+        );
+        TestHelpers::save_debug_image("write_canvas_smaller_than_texture", &buffer);
+        assert_eq!(buffer.get_pixel(0, 0), &Rgba([255, 255, 255, 255]));
+        assert_eq!(buffer.get_pixel(1, 1), &Rgba([255, 0, 0, 255]));
+        assert_eq!(buffer.get_pixel(7, 7), &Rgba([0, 0, 255, 255]));
+        assert_eq!(buffer.get_pixel(5, 5), &Rgba([0, 0, 0, 0]));
+    }
 }
@@ -73,7 +73,7 @@ impl TransformGenerator {
 
         let scale_factor = next_scale_transform.cols[0].mag();
 
-        if scale_factor < 1.0 || scale_factor > 50.0 {
+        if !(1.0..=50.0).contains(&scale_factor) {
             return;
         }
 
@@ -91,10 +91,7 @@ impl TransformGenerator {
         let base_model_transform = Mat4::from_translation(Vec3::new(-0.5, -0.5, 0.0));
         let translate = Mat4::from_translation(Vec3::new(self.offset.x, self.offset.y, 0.0));
 
-        let transform =
-            translate * self.scale_transform * scale_ratio * base_scale * base_model_transform;
-
-        transform
+        translate * self.scale_transform * scale_ratio * base_scale * base_model_transform
     }
 
     pub fn on_window_resize(&mut self, new_width: u32, new_height: u32) {
@@ -104,7 +101,7 @@ impl TransformGenerator {
     pub fn update(&mut self) {
         if !self.is_user_panning && self.pan_velocity != Vec2::zero() {
             self.pan_velocity *= 0.93;
-            self.offset = self.offset + self.pan_velocity;
+            self.offset += self.pan_velocity;
 
             if (self.pan_velocity.x.abs() + self.pan_velocity.y.abs()) < f32::EPSILON {
                 self.pan_velocity = Vec2::zero();